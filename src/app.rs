@@ -0,0 +1,611 @@
+use crate::{
+    base_request::{self, Hook, RequestResult, StepStatus, TestContext, TestPlan},
+    find_tk_yaml_files, include,
+};
+use log::LevelFilter;
+use ratatui::{
+    DefaultTerminal, Frame,
+    crossterm::event::{self, Event, KeyCode, KeyEventKind},
+    layout::{Constraint, Direction, Layout, Rect},
+    style::{Color, Modifier, Style},
+    text::{Line, Span},
+    widgets::{Block, Borders, List, ListItem, ListState, Paragraph, Wrap},
+};
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tokio::sync::mpsc;
+
+// Plan is a test file loaded into the app, along with the exports and results of its last runs.
+struct Plan {
+    file: PathBuf,
+    source: String,
+    items: Result<TestPlan, String>,
+    exports: HashMap<String, Value>,
+    results: HashMap<usize, RequestResult>,
+}
+
+#[derive(Clone, Copy)]
+enum Row {
+    Plan(usize),
+    Step(usize, usize),
+}
+
+enum Message {
+    StepFinished {
+        plan: usize,
+        step: usize,
+        result: Box<RequestResult>,
+        exports: HashMap<String, Value>,
+    },
+    StepFailed {
+        plan: usize,
+        step: usize,
+        error: String,
+    },
+    RunFinished {
+        hook_errors: Vec<String>,
+    },
+}
+
+struct App {
+    plans: Vec<Plan>,
+    rows: Vec<Row>,
+    list_state: ListState,
+    running: bool,
+    inspector_scroll: u16,
+    status: String,
+    tx: mpsc::UnboundedSender<Message>,
+    rx: mpsc::UnboundedReceiver<Message>,
+}
+
+// run opens the interactive terminal UI for the .tk.yaml files under dir.
+pub async fn run(dir: &Path) -> io::Result<()> {
+    // Log lines would be drawn over the UI, results are shown in the panes instead
+    log::set_max_level(LevelFilter::Off);
+    let mut app = App::new(dir);
+    // The event loop polls the terminal synchronously, so it runs on a blocking thread instead
+    // of holding up a worker of the runtime that sends the requests
+    tokio::task::spawn_blocking(move || {
+        let mut terminal = ratatui::init();
+        let result = app.event_loop(&mut terminal);
+        ratatui::restore();
+        result
+    })
+    .await
+    .unwrap_or_else(|err| Err(io::Error::other(err)))
+}
+
+impl App {
+    fn new(dir: &Path) -> Self {
+        let plans: Vec<Plan> = find_tk_yaml_files(dir)
+            .into_iter()
+            .map(|file| {
+                let source = fs::read_to_string(&file).unwrap_or_default();
+                let items =
+                    include::parse(&source, &file.to_string_lossy()).map_err(|e| e.to_string());
+                Plan {
+                    file,
+                    source,
+                    items,
+                    exports: HashMap::new(),
+                    results: HashMap::new(),
+                }
+            })
+            .collect();
+        let mut rows = Vec::new();
+        for (p, plan) in plans.iter().enumerate() {
+            rows.push(Row::Plan(p));
+            if let Ok(items) = &plan.items {
+                rows.extend((0..items.steps.len()).map(|s| Row::Step(p, s)));
+            }
+        }
+        let mut list_state = ListState::default();
+        if !rows.is_empty() {
+            list_state.select(Some(0));
+        }
+        let (tx, rx) = mpsc::unbounded_channel();
+        App {
+            plans,
+            rows,
+            list_state,
+            running: false,
+            inspector_scroll: 0,
+            status: String::new(),
+            tx,
+            rx,
+        }
+    }
+
+    fn event_loop(&mut self, terminal: &mut DefaultTerminal) -> io::Result<()> {
+        loop {
+            while let Ok(message) = self.rx.try_recv() {
+                self.apply(message);
+            }
+            terminal.draw(|frame| self.draw(frame))?;
+
+            if !event::poll(Duration::from_millis(50))? {
+                continue;
+            }
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.kind == KeyEventKind::Press && !self.handle_key(key.code) {
+                return Ok(());
+            }
+        }
+    }
+
+    // handle_key applies a key press, and tells whether the app keeps running
+    fn handle_key(&mut self, code: KeyCode) -> bool {
+        match code {
+            KeyCode::Char('q') | KeyCode::Esc => return false,
+            KeyCode::Down | KeyCode::Char('j') => self.move_selection(1),
+            KeyCode::Up | KeyCode::Char('k') => self.move_selection(-1),
+            KeyCode::PageDown => self.inspector_scroll = self.inspector_scroll.saturating_add(10),
+            KeyCode::PageUp => self.inspector_scroll = self.inspector_scroll.saturating_sub(10),
+            KeyCode::Enter | KeyCode::Char('r') => self.run_selected(),
+            KeyCode::Char('c') => self.clear_exports(),
+            _ => {}
+        }
+        true
+    }
+
+    fn selected_row(&self) -> Option<Row> {
+        self.list_state
+            .selected()
+            .and_then(|i| self.rows.get(i).copied())
+    }
+
+    fn move_selection(&mut self, delta: i64) {
+        if self.rows.is_empty() {
+            return;
+        }
+        let current = self.list_state.selected().unwrap_or(0) as i64;
+        let next = (current + delta).clamp(0, self.rows.len() as i64 - 1);
+        self.list_state.select(Some(next as usize));
+        self.inspector_scroll = 0;
+    }
+
+    // run_selected runs every step of a plan with a fresh set of exports, or a single step
+    // against the exports the plan has captured so far. A plan runs with its before_all and
+    // after_all hooks, and every step with the before_each and after_each hooks.
+    fn run_selected(&mut self) {
+        if self.running {
+            self.status = "A run is already in progress".into();
+            return;
+        }
+        let whole_plan = matches!(self.selected_row(), Some(Row::Plan(_)));
+        let (plan_index, steps, exports) = match self.selected_row() {
+            Some(Row::Plan(p)) => {
+                let count = self.plans[p]
+                    .items
+                    .as_ref()
+                    .map_or(0, |items| items.steps.len());
+                self.plans[p].results.clear();
+                (p, (0..count).collect::<Vec<_>>(), HashMap::new())
+            }
+            Some(Row::Step(p, s)) => (p, vec![s], self.plans[p].exports.clone()),
+            None => return,
+        };
+        let plan = &self.plans[plan_index];
+        let Ok(items) = plan.items.clone() else {
+            self.status = "This file could not be parsed".into();
+            return;
+        };
+        let ctx = TestContext {
+            file: plan.file.to_string_lossy().to_string(),
+            file_source: plan.source.clone(),
+            should_log: false,
+            ..Default::default()
+        };
+        self.running = true;
+        self.status = format!("Running {}", plan.file.display());
+        let tx = self.tx.clone();
+        tokio::spawn(async move {
+            let mut exports = exports;
+            let mut hook_errors = Vec::new();
+            if whole_plan {
+                hook_errors.extend(run_hook(&ctx, &items, Hook::BeforeAll, &mut exports).await);
+            }
+            // The steps are skipped when a before_all hook fails, like with `testkit test`
+            let steps = if hook_errors.is_empty() {
+                steps
+            } else {
+                vec![]
+            };
+            for step in steps {
+                if let Some(err) = run_hook(&ctx, &items, Hook::BeforeEach, &mut exports).await {
+                    hook_errors.push(err);
+                    hook_errors.extend(run_hook(&ctx, &items, Hook::AfterEach, &mut exports).await);
+                    continue;
+                }
+                let message = match base_request::run_item(
+                    &ctx,
+                    &items.steps[step],
+                    step as u32,
+                    None,
+                    &mut exports,
+                )
+                .await
+                {
+                    // A step with a for_each data set is shown by its first failing row, or its last one
+                    Ok(results) => Message::StepFinished {
+                        plan: plan_index,
+                        step,
+                        result: Box::new(
+                            results
+                                .into_iter()
+                                .reduce(|shown, result| match shown.status() {
+                                    StepStatus::Failed | StepStatus::Errored => shown,
+                                    _ => result,
+                                })
+                                .unwrap_or_default(),
+                        ),
+                        exports: exports.clone(),
+                    },
+                    Err(err) => Message::StepFailed {
+                        plan: plan_index,
+                        step,
+                        error: err.to_string(),
+                    },
+                };
+                let _ = tx.send(message);
+                hook_errors.extend(run_hook(&ctx, &items, Hook::AfterEach, &mut exports).await);
+            }
+            if whole_plan {
+                hook_errors.extend(run_hook(&ctx, &items, Hook::AfterAll, &mut exports).await);
+            }
+            let _ = tx.send(Message::RunFinished { hook_errors });
+        });
+    }
+
+    fn clear_exports(&mut self) {
+        let plan = match self.selected_row() {
+            Some(Row::Plan(p)) | Some(Row::Step(p, _)) => p,
+            None => return,
+        };
+        self.plans[plan].exports.clear();
+        self.status = format!("Cleared the exports of {}", self.plans[plan].file.display());
+    }
+
+    fn apply(&mut self, message: Message) {
+        match message {
+            Message::StepFinished {
+                plan,
+                step,
+                result,
+                exports,
+            } => {
+                self.plans[plan].exports = exports;
+                self.plans[plan].results.insert(step, *result);
+            }
+            Message::StepFailed { plan, step, error } => {
+                let result = RequestResult {
+                    step_index: step as u32,
+                    step_error: Some(error),
+                    ..Default::default()
+                };
+                self.plans[plan].results.insert(step, result);
+            }
+            Message::RunFinished { hook_errors } => {
+                self.running = false;
+                self.status = if hook_errors.is_empty() {
+                    "Run finished".into()
+                } else {
+                    format!("Run finished, {}", hook_errors.join(", "))
+                };
+            }
+        }
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [main, footer] = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Min(1), Constraint::Length(1)])
+            .areas(frame.area());
+        let [browser, details] = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(35), Constraint::Percentage(65)])
+            .areas(main);
+        let [assertions, inspector] = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Percentage(40), Constraint::Percentage(60)])
+            .areas(details);
+
+        self.draw_browser(frame, browser);
+        self.draw_assertions(frame, assertions);
+        self.draw_inspector(frame, inspector);
+
+        let help = "↑/↓ select  ⏎/r run plan or step  c clear exports  PgUp/PgDn scroll  q quit";
+        let footer_text = if self.status.is_empty() {
+            help.to_string()
+        } else {
+            format!("{}  │  {}", help, self.status)
+        };
+        frame.render_widget(
+            Paragraph::new(footer_text).style(Style::default().fg(Color::DarkGray)),
+            footer,
+        );
+    }
+
+    fn draw_browser(&mut self, frame: &mut Frame, area: Rect) {
+        let items: Vec<ListItem> = self
+            .rows
+            .iter()
+            .map(|row| match *row {
+                Row::Plan(p) => {
+                    let plan = &self.plans[p];
+                    let label = match &plan.items {
+                        Ok(items) => {
+                            format!("📄 {} ({} steps)", plan.file.display(), items.steps.len())
+                        }
+                        Err(_) => format!("💥 {} (invalid)", plan.file.display()),
+                    };
+                    ListItem::new(label).style(Style::default().add_modifier(Modifier::BOLD))
+                }
+                Row::Step(p, s) => {
+                    let plan = &self.plans[p];
+                    let item = &plan
+                        .items
+                        .as_ref()
+                        .expect("steps only exist for valid plans")
+                        .steps[s];
+                    let (method, url) = item.request.http_method.method_and_url();
+                    let title = item.title.clone().unwrap_or_else(|| url.clone());
+                    let (icon, color) = status_icon(plan.results.get(&s));
+                    ListItem::new(Line::from(vec![
+                        Span::raw("   "),
+                        Span::styled(icon, Style::default().fg(color)),
+                        Span::raw(format!(" {} ", method)),
+                        Span::raw(title),
+                    ]))
+                }
+            })
+            .collect();
+        let list = List::new(items)
+            .block(Block::default().borders(Borders::ALL).title(" Test plans "))
+            .highlight_style(Style::default().bg(Color::DarkGray));
+        frame.render_stateful_widget(list, area, &mut self.list_state);
+    }
+
+    fn draw_assertions(&self, frame: &mut Frame, area: Rect) {
+        let mut lines = Vec::new();
+        match self.selected_row() {
+            Some(Row::Plan(p)) => {
+                let plan = &self.plans[p];
+                match &plan.items {
+                    Err(err) => {
+                        lines.push(Line::styled(err.clone(), Style::default().fg(Color::Red)))
+                    }
+                    Ok(items) => {
+                        let count = |status| {
+                            plan.results
+                                .values()
+                                .filter(|r| r.status() == status)
+                                .count()
+                        };
+                        lines.push(Line::from(format!(
+                            "{} steps  ✅ {} passed  ❌ {} failed  💥 {} errored  ⏭ {} skipped",
+                            items.steps.len(),
+                            count(StepStatus::Passed),
+                            count(StepStatus::Failed),
+                            count(StepStatus::Errored),
+                            count(StepStatus::Skipped),
+                        )));
+                    }
+                }
+            }
+            Some(Row::Step(p, s)) => match self.plans[p].results.get(&s) {
+                None => lines.push(Line::from("Not run yet. Press ⏎ to run this step.")),
+                Some(result) => {
+                    if let Some(err) = &result.step_error {
+                        lines.push(Line::styled(err.clone(), Style::default().fg(Color::Red)));
+                    }
+                    if result.skipped {
+                        lines.push(Line::from(result.step_log.clone()));
+                    }
+                    for assertion in &result.assertions {
+                        let passed = assertion.passed && assertion.error.is_none();
+                        let (icon, color) = if passed {
+                            ("✅", Color::Green)
+                        } else {
+                            ("❌", Color::Red)
+                        };
+                        let mut spans = vec![
+                            Span::styled(
+                                format!("{} {: <12}", icon, assertion.kind),
+                                Style::default().fg(color),
+                            ),
+                            Span::raw(assertion.expr.clone()),
+                        ];
+                        if let Some(eval_expr) = &assertion.eval_expr {
+                            if eval_expr != &assertion.expr {
+                                spans.push(Span::styled(
+                                    format!("  ⮕  {}", eval_expr),
+                                    Style::default().fg(Color::DarkGray),
+                                ));
+                            }
+                        }
+                        lines.push(Line::from(spans));
+                        if let Some(advice) = assertion.error.as_ref().and_then(|e| e.advice()) {
+                            lines.push(Line::styled(
+                                format!("     {}", advice),
+                                Style::default().fg(Color::Yellow),
+                            ));
+                        }
+                    }
+                }
+            },
+            None => {}
+        }
+        let paragraph = Paragraph::new(lines)
+            .block(Block::default().borders(Borders::ALL).title(" Assertions "))
+            .wrap(Wrap { trim: false });
+        frame.render_widget(paragraph, area);
+    }
+
+    // draw_inspector shows the same request/response context that `dump: true` prints,
+    // followed by the exports captured so far.
+    fn draw_inspector(&self, frame: &mut Frame, area: Rect) {
+        let mut text = String::new();
+        let plan = match self.selected_row() {
+            Some(Row::Plan(p)) => Some(p),
+            Some(Row::Step(p, s)) => {
+                if let Some(result) = self.plans[p].results.get(&s) {
+                    if let Some(req) = &result.resolved_request {
+                        text.push_str(&format!("{} {}\n\n", req.method, req.url));
+                    }
+                    let context = serde_json::json!(&result.request);
+                    text.push_str(&serde_json::to_string_pretty(&context).unwrap_or_default());
+                    text.push_str("\n\n");
+                }
+                Some(p)
+            }
+            None => None,
+        };
+        if let Some(p) = plan {
+            let exports = serde_json::json!(&self.plans[p].exports);
+            text.push_str("Exports captured so far:\n");
+            text.push_str(&serde_json::to_string_pretty(&exports).unwrap_or_default());
+        }
+        let paragraph = Paragraph::new(text)
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title(" Response inspector "),
+            )
+            .wrap(Wrap { trim: false })
+            .scroll((self.inspector_scroll, 0));
+        frame.render_widget(paragraph, area);
+    }
+}
+
+// run_hook runs a hook of a plan, and tells why it failed, if it did. The results of hooks aren't
+// shown in the app.
+async fn run_hook(
+    ctx: &TestContext,
+    plan: &TestPlan,
+    hook: Hook,
+    exports: &mut HashMap<String, Value>,
+) -> Option<String> {
+    match base_request::run_hooks(ctx, hook, plan.hook(hook), None, exports).await {
+        Ok(results) if base_request::hooks_passed(&results) => None,
+        Ok(_) => Some(format!("a {} hook failed", hook.name())),
+        Err(err) => Some(format!("a {} hook failed: {}", hook.name(), err)),
+    }
+}
+
+fn status_icon(result: Option<&RequestResult>) -> (&'static str, Color) {
+    match result.map(|r| r.status()) {
+        None => ("·", Color::DarkGray),
+        Some(StepStatus::Passed) => ("✔", Color::Green),
+        Some(StepStatus::Failed) => ("✘", Color::Red),
+        Some(StepStatus::Errored) => ("!", Color::Yellow),
+        Some(StepStatus::Skipped) => ("-", Color::DarkGray),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::TestDir;
+    use httpmock::prelude::*;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_app_state() {
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.method(POST).path("/login");
+            then.status(200).json_body(json!({ "token": "abc" }));
+        });
+        server.mock(|when, then| {
+            when.method(GET).path("/todos");
+            then.status(500);
+        });
+        let dir = TestDir::new("app");
+        fs::write(
+            dir.join("a.tk.yaml"),
+            format!(
+                r#"
+- title: login
+  POST: {}
+  exports:
+    token: $.resp.json.token
+- title: list todos
+  GET: {}
+  asserts:
+    - ok: $.resp.status == 200
+"#,
+                server.url("/login"),
+                server.url("/todos")
+            ),
+        )
+        .unwrap();
+        fs::write(dir.join("b.tk.yaml"), "- title: [not, a, title]\n").unwrap();
+
+        let mut app = App::new(&dir);
+        // Every plan gets a row, followed by the rows of its steps when it could be parsed
+        assert!(matches!(
+            app.rows.as_slice(),
+            [Row::Plan(0), Row::Step(0, 0), Row::Step(0, 1), Row::Plan(1)]
+        ));
+        assert!(app.plans[1].items.is_err());
+
+        // The selection stays within the rows, and moving it resets the inspector scroll
+        assert!(app.handle_key(KeyCode::PageDown));
+        assert_eq!(app.inspector_scroll, 10);
+        app.handle_key(KeyCode::Up);
+        assert!(matches!(app.selected_row(), Some(Row::Plan(0))));
+        assert_eq!(app.inspector_scroll, 0);
+        for _ in 0..5 {
+            app.handle_key(KeyCode::Char('j'));
+        }
+        assert!(matches!(app.selected_row(), Some(Row::Plan(1))));
+        app.handle_key(KeyCode::Enter);
+        assert_eq!(app.status, "This file could not be parsed");
+        assert!(!app.running);
+
+        // Running a plan sends a message per step, then one when the run is over
+        app.list_state.select(Some(0));
+        app.handle_key(KeyCode::Char('r'));
+        assert!(app.running);
+        app.handle_key(KeyCode::Enter);
+        assert_eq!(app.status, "A run is already in progress");
+        loop {
+            let message = app.rx.recv().await.unwrap();
+            let finished = matches!(message, Message::RunFinished { .. });
+            app.apply(message);
+            if finished {
+                break;
+            }
+        }
+        assert!(!app.running);
+        assert_eq!(app.status, "Run finished");
+        let plan = &app.plans[0];
+        assert_eq!(plan.results[&0].status(), StepStatus::Passed);
+        assert_eq!(plan.results[&1].status(), StepStatus::Failed);
+        assert_eq!(plan.exports.get("token"), Some(&json!("abc")));
+
+        app.apply(Message::StepFailed {
+            plan: 0,
+            step: 1,
+            error: "invalid url".into(),
+        });
+        assert_eq!(app.plans[0].results[&1].status(), StepStatus::Errored);
+        app.apply(Message::RunFinished {
+            hook_errors: vec!["a before_all hook failed".into()],
+        });
+        assert_eq!(app.status, "Run finished, a before_all hook failed");
+
+        app.list_state.select(Some(2));
+        app.handle_key(KeyCode::Char('c'));
+        assert!(app.plans[0].exports.is_empty());
+        assert!(!app.handle_key(KeyCode::Char('q')));
+    }
+}
@@ -1,5 +1,11 @@
-use clap::{Parser, Subcommand};
-use std::path::PathBuf;
+use crate::{
+    environment::parse_var,
+    load::{Threshold, parse_duration},
+};
+use clap::{Parser, Subcommand, ValueEnum};
+use regex::Regex;
+use reqwest::Url;
+use std::{net::SocketAddr, path::PathBuf, str::FromStr, time::Duration};
 
 #[derive(Parser)]
 #[command(name = "testkit")]
@@ -13,6 +19,10 @@ pub struct Cli {
     /// Sets the log level to be used. Eg trace, debug, warn, info, error
     #[arg(short, long, default_value = "info")]
     pub log_level: String,
+
+    /// Loads the variables of a named environment from .env.<name>, on top of .env. Eg --env staging
+    #[arg(long, global = true)]
+    pub env: Option<String>,
 }
 
 #[derive(Subcommand)]
@@ -21,6 +31,185 @@ pub enum Commands {
         /// Sets the YAML test configuration file
         #[arg(short, long)]
         file: Option<PathBuf>,
+
+        /// Writes the results in the given format, in addition to the console output
+        #[arg(short, long, value_enum)]
+        reporter: Option<Reporter>,
+
+        /// Sets the file the reporter writes to. Defaults to stdout
+        #[arg(short, long, requires = "reporter")]
+        output: Option<PathBuf>,
+
+        /// Only runs steps with one of the given tags. Can be repeated or comma separated
+        #[arg(long, value_delimiter = ',')]
+        tag: Vec<String>,
+
+        /// Skips steps with any of the given tags. Can be repeated or comma separated
+        #[arg(long, value_delimiter = ',')]
+        exclude_tag: Vec<String>,
+
+        /// Only runs steps whose title matches the given regex
+        #[arg(long, value_parser = Regex::new)]
+        grep: Option<Regex>,
+
+        /// Sets how many test files are run concurrently
+        #[arg(short, long, default_value_t = 1)]
+        jobs: usize,
+
+        /// Re-runs a plan whenever its file is saved, and every plan when .env changes
+        #[arg(short, long)]
+        watch: bool,
+
+        /// Sets a variable for every plan, eg `--var tenantId=42`. Steps can't export over it. Can be repeated
+        #[arg(long = "var", value_name = "NAME=VALUE", value_parser = parse_var)]
+        vars: Vec<(String, String)>,
+
+        /// Reads variables for every plan from a JSON or YAML object. --var takes precedence over it
+        #[arg(long)]
+        vars_file: Option<PathBuf>,
+    },
+    /// Runs a test plan as a load test, with virtual users running it in a loop
+    Load {
+        /// The YAML test file to run
+        file: PathBuf,
+
+        /// Sets how many virtual users run the plan at the same time
+        #[arg(long, default_value_t = 10)]
+        vus: usize,
+
+        /// Sets how long the test runs, eg 30s, 2m or 1m30s
+        #[arg(short, long, default_value = "30s", value_parser = parse_duration)]
+        duration: Duration,
+
+        /// Starts the virtual users one after another over this long, instead of all at once
+        #[arg(long, default_value = "0s", value_parser = parse_duration)]
+        ramp_up: Duration,
+
+        /// Fails the run when the results break it, eg `p95<300ms`, `avg<200ms`, `error_rate<1%` or `rps>100`. Can be repeated
+        #[arg(short, long, value_parser = Threshold::from_str)]
+        threshold: Vec<Threshold>,
+
+        /// Sets a variable for the plan, eg `--var tenantId=42`. Steps can't export over it. Can be repeated
+        #[arg(long = "var", value_name = "NAME=VALUE", value_parser = parse_var)]
+        vars: Vec<(String, String)>,
+
+        /// Reads variables for the plan from a JSON or YAML object. --var takes precedence over it
+        #[arg(long)]
+        vars_file: Option<PathBuf>,
+    },
+    /// Checks test files for mistakes without sending any requests
+    Validate {
+        /// Sets the YAML test configuration file. Defaults to every .tk.yaml file in the current directory
+        #[arg(short, long)]
+        file: Option<PathBuf>,
+
+        /// Sets a variable for every plan, eg `--var tenantId=42`, so steps can use it without an earlier step exporting it. Can be repeated
+        #[arg(long = "var", value_name = "NAME=VALUE", value_parser = parse_var)]
+        vars: Vec<(String, String)>,
+
+        /// Reads variables for every plan from a JSON or YAML object
+        #[arg(long)]
+        vars_file: Option<PathBuf>,
+    },
+    /// Generates a test plan from another format
+    Import {
+        #[command(subcommand)]
+        source: ImportSource,
+    },
+    /// Runs a reverse proxy to the service under test, and records the requests sent through it as a test plan
+    Record {
+        /// The address the proxy listens on
+        #[arg(long, default_value = "127.0.0.1:8899")]
+        listen: SocketAddr,
+
+        /// The url of the service under test, eg http://localhost:3000
+        #[arg(long)]
+        target: Url,
+
+        /// Sets the file the test plan is written to
+        #[arg(short, long, default_value = "recorded.tk.yaml")]
+        output: PathBuf,
     },
+    /// Renders the steps of a test file in another format
+    Export {
+        #[command(subcommand)]
+        target: ExportTarget,
+    },
+    /// Prints the JSON Schema of the .tk.yaml format, for editor autocompletion and checks
+    Schema {},
+    /// Opens an interactive terminal UI to browse, run and inspect test plans
     App {},
 }
+
+#[derive(Subcommand)]
+pub enum ImportSource {
+    /// Generates one step per operation of an OpenAPI 3 spec
+    Openapi {
+        /// The OpenAPI spec, as YAML or JSON
+        spec: PathBuf,
+
+        /// Sets the file the test plan is written to. Defaults to stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Converts a Postman collection. Every folder becomes a test plan of its own
+    Postman {
+        /// The collection, exported as Collection v2.1
+        collection: PathBuf,
+
+        /// Sets the file, or the directory of files, the test plans are written to. Defaults to stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Converts curl commands, eg as copied from the browser devtools. Several commands are imported as several steps
+    Curl {
+        /// The curl command, or - to read the commands from stdin
+        command: String,
+
+        /// Sets the file the test plan is written to. Defaults to stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Converts a HAR file recorded in the browser devtools. Values a response returns and a later request sends become exports
+    Har {
+        /// The HAR file
+        har: PathBuf,
+
+        /// Only imports requests to these hosts and their subdomains, eg `--host api.example.com`
+        #[arg(long, value_delimiter = ',')]
+        host: Vec<String>,
+
+        /// Drops scripts, stylesheets, images, fonts and media
+        #[arg(long)]
+        skip_assets: bool,
+
+        /// Sets the file the test plan is written to. Defaults to stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ExportTarget {
+    /// Prints every step as a curl command, with env variables substituted
+    Curl {
+        /// The test file to export
+        file: PathBuf,
+
+        /// Sets the file the commands are written to. Defaults to stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum Reporter {
+    /// JUnit XML, as read by most CI systems
+    Junit,
+    /// A single JSON document written at the end of the run
+    Json,
+    /// One JSON event per line, written as each step finishes
+    Ndjson,
+    /// A single self-contained HTML page
+    Html,
+}
@@ -1,3 +1,7 @@
+use crate::{
+    data,
+    include::{self, Include},
+};
 use chrono::{NaiveDate, NaiveDateTime};
 use jsonpath_lib::select;
 use miette::{Diagnostic, GraphicalReportHandler, GraphicalTheme, NamedSource, Report, SourceSpan};
@@ -12,21 +16,172 @@ use std::{
     collections::HashMap,
     env::{self, VarError},
     f64::consts::E,
-    time::Duration,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
 };
 use thiserror::Error;
 
 #[serde_as]
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct TestItem {
-    title: Option<String>,
-    dump: Option<bool>,
+    pub title: Option<String>,
+    pub dump: Option<bool>,
     #[serde(flatten)]
-    request: RequestConfig,
+    pub request: RequestConfig,
     #[serde(default)]
     #[serde(with = "serde_yaml::with::singleton_map_recursive")]
-    asserts: Option<Vec<Assert>>,
-    exports: Option<HashMap<String, String>>,
+    pub asserts: Option<Vec<Assert>>,
+    pub exports: Option<HashMap<String, String>>,
+    pub tags: Option<Vec<String>>,
+    pub retry: Option<Retry>,
+    // The step only runs when `if` holds, and is skipped when `skip_if` holds. Both are
+    // evaluated before the request is sent, so they can use exports and env variables.
+    #[serde(rename = "if")]
+    pub run_if: Option<String>,
+    pub skip_if: Option<String>,
+    pub for_each: Option<ForEach>,
+    // Set on the steps of included files, see include::parse
+    #[serde(skip)]
+    pub include: Option<Include>,
+}
+
+// The keys of a step besides its HTTP method key, the serde names of TestItem and RequestConfig
+pub const STEP_KEYS: &[&str] = &[
+    "title",
+    "dump",
+    "asserts",
+    "exports",
+    "tags",
+    "method",
+    "url",
+    "retry",
+    "if",
+    "skip_if",
+    "for_each",
+    "headers",
+    "json",
+    "params",
+    "disabled",
+    "httpVersion",
+    "timeout",
+    "follow_redirects",
+    "max_redirects",
+    "ignore_ssl_errors",
+    "raw",
+    "requestBody",
+];
+
+// TestPlan is a test file: its steps, and the hooks that run around them. A file that is just
+// a list of steps has no hooks. The after_each and after_all hooks run even when steps fail, so
+// they can clean up what the steps created.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TestPlan<T = TestItem> {
+    #[serde(default)]
+    pub before_all: Vec<T>,
+    #[serde(default)]
+    pub before_each: Vec<T>,
+    pub steps: Vec<T>,
+    #[serde(default)]
+    pub after_each: Vec<T>,
+    #[serde(default)]
+    pub after_all: Vec<T>,
+}
+
+impl<T> TestPlan<T> {
+    pub fn from_steps(steps: Vec<T>) -> Self {
+        TestPlan {
+            before_all: Vec::new(),
+            before_each: Vec::new(),
+            steps,
+            after_each: Vec::new(),
+            after_all: Vec::new(),
+        }
+    }
+
+    pub fn hook(&self, hook: Hook) -> &[T] {
+        match hook {
+            Hook::BeforeAll => &self.before_all,
+            Hook::BeforeEach => &self.before_each,
+            Hook::AfterEach => &self.after_each,
+            Hook::AfterAll => &self.after_all,
+        }
+    }
+
+    // sections returns the hooks and steps by their key, in the order they run
+    pub fn sections(&self) -> [(&'static str, &Vec<T>); 5] {
+        [
+            (Hook::BeforeAll.name(), &self.before_all),
+            (Hook::BeforeEach.name(), &self.before_each),
+            ("steps", &self.steps),
+            (Hook::AfterEach.name(), &self.after_each),
+            (Hook::AfterAll.name(), &self.after_all),
+        ]
+    }
+}
+
+// Hook is the hook a step belongs to. The results of hook steps are reported apart from the
+// steps of the plan.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Hook {
+    BeforeAll,
+    BeforeEach,
+    AfterEach,
+    AfterAll,
+}
+
+impl Hook {
+    pub fn name(self) -> &'static str {
+        match self {
+            Hook::BeforeAll => "before_all",
+            Hook::BeforeEach => "before_each",
+            Hook::AfterEach => "after_each",
+            Hook::AfterAll => "after_all",
+        }
+    }
+}
+
+// ForEach is the data set of a step that runs once per row: an inline list of rows, or the
+// path of a CSV, JSON or YAML file relative to the test file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ForEach {
+    File(String),
+    Rows(Vec<data::Row>),
+}
+
+// Retry re-sends a step until its `until` expression holds, eg to poll the status of a job,
+// and only then evaluates the asserts. Without `until`, the step is re-sent until it passes.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Retry {
+    pub attempts: Option<u32>,
+    pub delay_ms: Option<u64>,
+    pub backoff: Option<Backoff>,
+    pub until: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Backoff {
+    #[default]
+    Constant,
+    Exponential,
+}
+
+impl Retry {
+    pub fn attempts(&self) -> u32 {
+        self.attempts.unwrap_or(3).max(1)
+    }
+
+    // delay is how long to wait after the given attempt, counting from 1
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let delay = Duration::from_millis(self.delay_ms.unwrap_or(1000));
+        match self.backoff.unwrap_or_default() {
+            Backoff::Constant => delay,
+            Backoff::Exponential => delay.saturating_mul(2u32.saturating_pow(attempt - 1)),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -61,6 +216,24 @@ pub enum Assert {
     NotRegexMatch(String),
 }
 
+// The keys assertions are written with, in the order of the Assert enum. See Assert::kind
+pub const ASSERT_KINDS: &[&str] = &[
+    "ok",
+    "array",
+    "empty",
+    "string",
+    "number",
+    "boolean",
+    "null",
+    "exists",
+    "date",
+    "notEmpty",
+    "contains",
+    "notContains",
+    "regexMatch",
+    "noRegexMatch",
+];
+
 #[derive(Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct ConfigVariable {
@@ -81,6 +254,7 @@ pub struct RequestConfig {
     pub timeout: Option<u64>,
     #[serde[rename = "follow_redirects"]]
     pub follow_redirects: Option<bool>,
+    pub max_redirects: Option<u64>,
     #[serde[rename = "ignore_ssl_errors"]]
     pub ignore_ssl_errors: Option<bool>,
     pub raw: Option<String>,
@@ -88,21 +262,186 @@ pub struct RequestConfig {
     pub request_body: Option<HashMap<String, String>>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Header {
-    name: String,
-    value: String,
+    pub name: String,
+    pub value: String,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
-#[serde(rename_all = "UPPERCASE")]
+// HttpMethod is the method and url of a step. The common methods are written as a key with the
+// url, eg `GET: http://localhost/todos`. Any other method is written as `method: PURGE` along
+// with `url:`.
+#[derive(Clone, Debug, PartialEq)]
 pub enum HttpMethod {
     GET(String),
     POST(String),
     DELETE(String),
-    PUT(String), // Add other HTTP methods as needed
+    PUT(String),
     PATCH(String),
     HEAD(String),
+    OPTIONS(String),
+    TRACE(String),
+    CONNECT(String),
+    Custom { method: String, url: String },
+}
+
+// The methods that can be written as a key of a step
+pub const HTTP_METHODS: &[&str] = &[
+    "GET", "POST", "DELETE", "PUT", "PATCH", "HEAD", "OPTIONS", "TRACE", "CONNECT",
+];
+
+impl Assert {
+    // kind is the key the assertion is written with in a test file. Eg `ok` or `regexMatch`
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Assert::IsOk(_) => "ok",
+            Assert::IsArray(_) => "array",
+            Assert::IsEmpty(_) => "empty",
+            Assert::IsString(_) => "string",
+            Assert::IsNumber(_) => "number",
+            Assert::IsBoolean(_) => "boolean",
+            Assert::IsNull(_) => "null",
+            Assert::Exists(_) => "exists",
+            Assert::IsDate(_) => "date",
+            Assert::NotEmpty(_) => "notEmpty",
+            Assert::Contains(_) => "contains",
+            Assert::NotContains(_) => "notContains",
+            Assert::RegexMatch(_) => "regexMatch",
+            Assert::NotRegexMatch(_) => "noRegexMatch",
+        }
+    }
+
+    pub fn expr(&self) -> &String {
+        match self {
+            Assert::IsOk(expr)
+            | Assert::IsArray(expr)
+            | Assert::IsEmpty(expr)
+            | Assert::IsString(expr)
+            | Assert::IsNumber(expr)
+            | Assert::IsBoolean(expr)
+            | Assert::IsNull(expr)
+            | Assert::Exists(expr)
+            | Assert::IsDate(expr)
+            | Assert::NotEmpty(expr)
+            | Assert::Contains(expr)
+            | Assert::NotContains(expr)
+            | Assert::RegexMatch(expr)
+            | Assert::NotRegexMatch(expr) => expr,
+        }
+    }
+}
+
+impl HttpMethod {
+    // new is the inverse of method_and_url. The common methods are matched regardless of case,
+    // other methods are kept as written. It's None for names that aren't valid HTTP methods.
+    pub fn new(method: &str, url: String) -> Option<Self> {
+        match method.to_uppercase().as_str() {
+            "GET" => Some(HttpMethod::GET(url)),
+            "POST" => Some(HttpMethod::POST(url)),
+            "DELETE" => Some(HttpMethod::DELETE(url)),
+            "PUT" => Some(HttpMethod::PUT(url)),
+            "PATCH" => Some(HttpMethod::PATCH(url)),
+            "HEAD" => Some(HttpMethod::HEAD(url)),
+            "OPTIONS" => Some(HttpMethod::OPTIONS(url)),
+            "TRACE" => Some(HttpMethod::TRACE(url)),
+            "CONNECT" => Some(HttpMethod::CONNECT(url)),
+            _ if reqwest::Method::from_bytes(method.as_bytes()).is_ok() => {
+                Some(HttpMethod::Custom {
+                    method: method.to_string(),
+                    url,
+                })
+            }
+            _ => None,
+        }
+    }
+
+    pub fn method_and_url(&self) -> (&str, &String) {
+        match self {
+            HttpMethod::GET(url) => ("GET", url),
+            HttpMethod::POST(url) => ("POST", url),
+            HttpMethod::DELETE(url) => ("DELETE", url),
+            HttpMethod::PUT(url) => ("PUT", url),
+            HttpMethod::PATCH(url) => ("PATCH", url),
+            HttpMethod::HEAD(url) => ("HEAD", url),
+            HttpMethod::OPTIONS(url) => ("OPTIONS", url),
+            HttpMethod::TRACE(url) => ("TRACE", url),
+            HttpMethod::CONNECT(url) => ("CONNECT", url),
+            HttpMethod::Custom { method, url } => (method, url),
+        }
+    }
+}
+
+// HttpMethod is flattened into the step, so it's read from and written to the keys of the step
+impl Serialize for HttpMethod {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeMap;
+        let mut map = serializer.serialize_map(None)?;
+        match self {
+            HttpMethod::Custom { method, url } => {
+                map.serialize_entry("method", method)?;
+                map.serialize_entry("url", url)?;
+            }
+            _ => {
+                let (method, url) = self.method_and_url();
+                map.serialize_entry(method, url)?;
+            }
+        }
+        map.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for HttpMethod {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_map(HttpMethodVisitor)
+    }
+}
+
+struct HttpMethodVisitor;
+
+impl<'de> serde::de::Visitor<'de> for HttpMethodVisitor {
+    type Value = HttpMethod;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("an HTTP method with the request url, eg `GET: <url>`, or `method` and `url`")
+    }
+
+    fn visit_map<A: serde::de::MapAccess<'de>>(self, mut map: A) -> Result<HttpMethod, A::Error> {
+        use serde::de::Error;
+        let (mut shorthand, mut method, mut url) = (None, None, None);
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "method" => method = Some(map.next_value::<String>()?),
+                "url" => url = Some(map.next_value::<String>()?),
+                // Like the derived enum before, the first method key wins
+                key if HTTP_METHODS.contains(&key) && shorthand.is_none() => {
+                    shorthand = HttpMethod::new(key, map.next_value()?)
+                }
+                _ => {
+                    map.next_value::<serde::de::IgnoredAny>()?;
+                }
+            }
+        }
+        match (shorthand, method, url) {
+            // The request of a dump has both, see RequestAndResponse
+            (Some(shorthand), Some(method), None)
+                if shorthand.method_and_url().0 == method.to_uppercase() =>
+            {
+                Ok(shorthand)
+            }
+            (Some(_), Some(_), _) | (Some(_), _, Some(_)) => Err(A::Error::custom(
+                "a step has either a method key like `GET: <url>`, or `method` and `url`, not both",
+            )),
+            (Some(shorthand), None, None) => Ok(shorthand),
+            (None, Some(method), Some(url)) => HttpMethod::new(&method, url)
+                .ok_or_else(|| A::Error::custom(format!("invalid HTTP method `{}`", method))),
+            (None, Some(_), None) => Err(A::Error::custom("`method` needs a `url`")),
+            (None, None, Some(_)) => Err(A::Error::custom("`url` needs a `method`")),
+            (None, None, None) => Err(A::Error::custom(format!(
+                "missing the HTTP method, add one of {} with the request url, or `method` and `url`",
+                HTTP_METHODS.join(", ")
+            ))),
+        }
+    }
 }
 
 impl Default for HttpMethod {
@@ -115,23 +454,231 @@ impl Default for HttpMethod {
 pub struct RequestResult {
     pub step_name: Option<String>,
     pub step_index: u32,
-    pub assert_results: Vec<Result<bool, AssertionError>>,
     pub request: RequestAndResponse,
     pub step_log: String,
     pub step_error: Option<String>,
+    pub skipped: bool,
+    pub assertions: Vec<AssertionResult>,
+    pub timings: StepTimings,
+    pub resolved_request: Option<ResolvedRequest>,
+    // Every sending of a step with a `retry` block, in order. Empty for other steps.
+    pub attempts: Vec<Attempt>,
+    // Set on the results of hook steps
+    pub hook: Option<Hook>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Attempt {
+    pub status: Option<u16>,
+    pub error: Option<String>,
+    // Whether `until` held, or the step passed when the retry block has no `until`
+    pub done: bool,
+    pub response_ms: Option<f64>,
+}
+
+// ResolvedRequest is the request as it was sent, after exports and env variables were substituted.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ResolvedRequest {
+    pub method: String,
+    pub url: String,
+    pub headers: Vec<Header>,
+    pub body: Option<String>,
+}
+
+impl ResolvedRequest {
+    fn from_request(request: &reqwest::Request) -> Self {
+        ResolvedRequest {
+            method: request.method().to_string(),
+            url: request.url().to_string(),
+            headers: request
+                .headers()
+                .iter()
+                .map(|(name, value)| Header {
+                    name: name.to_string(),
+                    value: String::from_utf8_lossy(value.as_bytes()).into_owned(),
+                })
+                .collect(),
+            body: request
+                .body()
+                .and_then(|body| body.as_bytes())
+                .map(|bytes| String::from_utf8_lossy(bytes).into_owned()),
+        }
+    }
+
+    // to_curl renders the request as a curl command, so a failing step can be sent again
+    // outside of testkit. config adds the client options of the step.
+    pub fn to_curl(&self, config: &RequestConfig) -> String {
+        let command = match self.method.as_str() {
+            "GET" => "curl".to_string(),
+            // curl waits for a body that never comes with `-X HEAD`
+            "HEAD" => "curl --head".to_string(),
+            method => format!("curl -X {}", method),
+        };
+        let mut args = vec![format!("{} {}", command, shell_quote(&self.url))];
+        for header in &self.headers {
+            args.push(format!(
+                "-H {}",
+                shell_quote(&format!("{}: {}", header.name, header.value))
+            ));
+        }
+        if let Some(body) = &self.body {
+            args.push(format!("--data-raw {}", shell_quote(body)));
+        }
+        // testkit follows redirects unless asked not to, curl only when asked to
+        if config.follow_redirects != Some(false) {
+            args.push("-L".to_string());
+            if let Some(max) = config.max_redirects {
+                args.push(format!("--max-redirs {}", max));
+            }
+        }
+        if config.ignore_ssl_errors.unwrap_or(false) {
+            args.push("-k".to_string());
+        }
+        if config.http_version.as_deref() == Some("http-2") {
+            args.push("--http2-prior-knowledge".to_string());
+        }
+        if let Some(timeout) = config.timeout {
+            args.push(format!("-m {}", timeout));
+        }
+        args.join(" \\\n  ")
+    }
+}
+
+// shell_quote wraps a value in single quotes, which the shell passes on as is
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+// AssertionResult is the detailed outcome of a single assertion, in the order they were declared.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct AssertionResult {
+    pub kind: String,
+    pub expr: String,
+    // The expression after jsonpaths, exports and env variables were substituted, when evaluated
+    pub eval_expr: Option<String>,
+    pub passed: bool,
+    pub error: Option<AssertionError>,
+}
+
+impl AssertionResult {
+    pub fn result(&self) -> Result<bool, AssertionError> {
+        match &self.error {
+            Some(err) => Err(err.clone()),
+            None => Ok(self.passed),
+        }
+    }
+}
+
+// StepTimings are wall clock timings of a step, in milliseconds.
+// response_ms is the time until the response headers were received and body_ms the time
+// spent reading the response body.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct StepTimings {
+    pub started_at: Option<String>,
+    pub response_ms: Option<f64>,
+    pub body_ms: Option<f64>,
+    pub total_ms: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StepStatus {
+    Passed,
+    Failed,
+    Errored,
+    Skipped,
+}
+
+impl RequestResult {
+    // A step fails if any of its assertions evaluated to false or could not be evaluated.
+    pub fn status(&self) -> StepStatus {
+        if self.skipped {
+            StepStatus::Skipped
+        } else if self.step_error.is_some() {
+            StepStatus::Errored
+        } else if self
+            .assertions
+            .iter()
+            .any(|a| !a.passed || a.error.is_some())
+        {
+            StepStatus::Failed
+        } else {
+            StepStatus::Passed
+        }
+    }
+
+    // assert_results are the outcomes of the assertions, in the order they were declared
+    pub fn assert_results(&self) -> Vec<Result<bool, AssertionError>> {
+        self.assertions
+            .iter()
+            .map(AssertionResult::result)
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Default)]
 pub struct RequestAndResponse {
-    req: RequestConfig,
-    resp: ResponseObject,
+    pub req: RequestConfig,
+    pub resp: ResponseObject,
+}
+
+// RequestAndResponse is written with the method of the request as `req.method`, whichever way
+// the step declares it, so asserts can read it as `$.req.method`.
+impl Serialize for RequestAndResponse {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::{Error, SerializeStruct};
+        let mut req = serde_json::to_value(&self.req).map_err(S::Error::custom)?;
+        let (method, _) = self.req.http_method.method_and_url();
+        req["method"] = Value::String(method.to_string());
+        let mut state = serializer.serialize_struct("RequestAndResponse", 2)?;
+        state.serialize_field("req", &req)?;
+        state.serialize_field("resp", &self.resp)?;
+        state.end()
+    }
 }
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct ResponseObject {
-    status: u16,
-    headers: Value,
-    json: Value,
-    raw: String,
+    pub status: u16,
+    pub headers: Value,
+    pub json: Value,
+    pub raw: String,
+    #[serde(default)]
+    pub redirects: Vec<Redirect>,
+}
+
+// Redirect is a redirect response that was followed on the way to the final response
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
+pub struct Redirect {
+    pub url: String,
+    pub status: u16,
+}
+
+// How many redirects are followed when a step has no `max_redirects`, the same as reqwest
+const DEFAULT_MAX_REDIRECTS: u64 = 10;
+
+// redirect_policy stops at the first redirect when `follow_redirects` is false, and otherwise
+// follows up to `max_redirects` of them, recording each one in `redirects`
+fn redirect_policy(
+    config: &RequestConfig,
+    redirects: Arc<Mutex<Vec<Redirect>>>,
+) -> reqwest::redirect::Policy {
+    if config.follow_redirects == Some(false) {
+        return reqwest::redirect::Policy::none();
+    }
+    let max = config.max_redirects.unwrap_or(DEFAULT_MAX_REDIRECTS) as usize;
+    reqwest::redirect::Policy::custom(move |attempt| {
+        // previous starts with the url of the request, then holds every redirect followed so far
+        if attempt.previous().len() > max {
+            return attempt.error(format!("more than {} redirects (max_redirects)", max));
+        }
+        if let Some(url) = attempt.previous().last() {
+            redirects.lock().unwrap().push(Redirect {
+                url: url.to_string(),
+                status: attempt.status().as_u16(),
+            });
+        }
+        attempt.follow()
+    })
 }
 
 #[derive(Error, Serialize, Clone, Debug, Diagnostic)]
@@ -145,22 +692,87 @@ pub struct AssertionError {
     #[help]
     advice: Option<String>,
     #[source_code]
-    #[serde(skip_serializing)]
+    #[serde(serialize_with = "serialize_named_source")]
     src: NamedSource<String>,
     #[label("This jsonpath here")]
-    #[serde(skip_serializing)]
+    #[serde(serialize_with = "serialize_source_span")]
     bad_bit: SourceSpan,
 }
 
-fn report_error(diag: Report) -> String {
+impl AssertionError {
+    pub fn advice(&self) -> Option<&str> {
+        self.advice.as_deref()
+    }
+}
+
+fn serialize_named_source<S: serde::Serializer>(
+    src: &NamedSource<String>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    use serde::ser::SerializeStruct;
+    let mut state = serializer.serialize_struct("NamedSource", 2)?;
+    state.serialize_field("name", src.name())?;
+    state.serialize_field("source", src.inner())?;
+    state.end()
+}
+
+fn serialize_source_span<S: serde::Serializer>(
+    span: &SourceSpan,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    use serde::ser::SerializeStruct;
+    let mut state = serializer.serialize_struct("SourceSpan", 2)?;
+    state.serialize_field("offset", &span.offset())?;
+    state.serialize_field("length", &span.len())?;
+    state.end()
+}
+
+pub(crate) fn report_error(diag: Report) -> String {
+    render_report(&diag, GraphicalTheme::unicode())
+}
+
+// report_text renders a report without colours, for the step logs that end up in reports
+pub(crate) fn report_text(diag: &Report) -> String {
+    render_report(diag, GraphicalTheme::unicode_nocolor())
+}
+
+fn render_report(diag: &Report, theme: GraphicalTheme) -> String {
     let mut out = String::new();
-    GraphicalReportHandler::new_themed(GraphicalTheme::unicode())
+    GraphicalReportHandler::new_themed(theme)
         .with_width(80)
         .render_report(&mut out, diag.as_ref())
         .unwrap();
     out
 }
 
+// StepFilter selects which steps of a plan are run, via the --tag, --exclude-tag and --grep flags.
+// Steps that are not selected are reported as skipped.
+#[derive(Default, Clone, Debug)]
+pub struct StepFilter {
+    pub tags: Vec<String>,
+    pub exclude_tags: Vec<String>,
+    pub grep: Option<Regex>,
+}
+
+impl StepFilter {
+    fn matches(&self, test_item: &TestItem) -> bool {
+        let tags = test_item.tags.clone().unwrap_or_default();
+        if !self.tags.is_empty() && !self.tags.iter().any(|t| tags.contains(t)) {
+            return false;
+        }
+        if self.exclude_tags.iter().any(|t| tags.contains(t)) {
+            return false;
+        }
+        if let Some(grep) = &self.grep {
+            return grep.is_match(test_item.title.as_deref().unwrap_or_default());
+        }
+        true
+    }
+}
+
+// StepCallback is called with the file name and result of every step as soon as the step finishes.
+pub type StepCallback = Arc<dyn Fn(&str, &RequestResult) + Send + Sync>;
+
 #[derive(Default, Clone)]
 pub struct TestContext {
     pub plan: Option<String>,
@@ -170,17 +782,22 @@ pub struct TestContext {
     pub file: String,
     pub file_source: String,
     pub should_log: bool,
+    pub on_step: Option<StepCallback>,
+    pub filter: StepFilter,
+    // vars are set with --var and --vars-file. They seed the exports of the plan, and steps
+    // can't export over them, so a CI job can pin a value for the whole run.
+    pub vars: HashMap<String, Value>,
 }
 
 pub async fn run(
     ctx: TestContext,
     exec_string: String,
 ) -> Result<Vec<RequestResult>, Box<dyn std::error::Error>> {
-    let test_items: Vec<TestItem> = serde_yaml::from_str(&exec_string)?;
+    let plan = include::parse(&exec_string, &ctx.file)?;
 
-    log::debug!(target:"testkit","test_items: {:#?}", test_items);
+    log::debug!(target:"testkit","test_plan: {:#?}", plan);
     let should_log = ctx.should_log;
-    let result = base_request(ctx.clone(), &test_items, None, None).await;
+    let result = base_request(ctx.clone(), &plan, None, None).await;
     match result {
         Ok(res) => {
             if should_log {
@@ -203,10 +820,14 @@ pub async fn run_json(
     col_id: Option<String>,
     local_vars: Option<Vec<ConfigVariable>>,
 ) -> Result<Vec<RequestResult>, Box<dyn std::error::Error>> {
-    let test_items: Vec<TestItem> = serde_json::from_str(&exec_string)?;
-    log::debug!(target:"testkit","test_items: {:#?}", test_items);
+    // Like test files, the JSON is a list of steps or a plan with hooks
+    let plan = match serde_json::from_str::<Value>(&exec_string)? {
+        Value::Object(_) => serde_json::from_str(&exec_string)?,
+        _ => TestPlan::from_steps(serde_json::from_str(&exec_string)?),
+    };
+    log::debug!(target:"testkit","test_plan: {:#?}", plan);
     let should_log = ctx.should_log;
-    let result = base_request(ctx.clone(), &test_items, col_id, local_vars).await;
+    let result = base_request(ctx.clone(), &plan, col_id, local_vars).await;
     match result {
         Ok(res) => {
             if should_log {
@@ -227,12 +848,10 @@ pub async fn run_json(
 // Logging in place allows tracking of the results earliers
 pub async fn base_request(
     ctx: TestContext,
-    test_items: &Vec<TestItem>,
+    plan: &TestPlan,
     col_id: Option<String>,
     local_vars: Option<Vec<ConfigVariable>>,
 ) -> Result<Vec<RequestResult>, Box<dyn std::error::Error>> {
-    let should_log = ctx.should_log;
-
     let mut results: Vec<RequestResult> = Vec::new();
     let mut exports_map: HashMap<String, Value> = HashMap::new();
 
@@ -241,122 +860,526 @@ pub async fn base_request(
             exports_map.insert(var.variable_name, Value::String(var.variable_value));
         }
     }
+    exports_map.extend(ctx.vars.clone());
+
+    let col_id = col_id.as_ref();
+    // The error is kept as a string, as the after_all hooks are awaited before it's returned
+    let steps_result = run_steps(&ctx, plan, col_id, &mut exports_map, &mut results)
+        .await
+        .map_err(|err| err.to_string());
+    let after_all = run_hooks(
+        &ctx,
+        Hook::AfterAll,
+        &plan.after_all,
+        col_id,
+        &mut exports_map,
+    )
+    .await
+    .map_err(|err| err.to_string());
+    steps_result?;
+    for step_result in after_all? {
+        finish_step(&ctx, &mut results, step_result);
+    }
+    Ok(results)
+}
 
-    for (i, test_item) in test_items.iter().enumerate() {
-        let mut client = reqwest::Client::builder().connection_verbose(true);
+// run_steps runs the before_all hooks, then every step between the before_each and after_each
+// hooks. The steps are skipped when a before_all hook doesn't pass, and a step is skipped when
+// one of its before_each hooks doesn't.
+async fn run_steps(
+    ctx: &TestContext,
+    plan: &TestPlan,
+    col_id: Option<&String>,
+    exports_map: &mut HashMap<String, Value>,
+    results: &mut Vec<RequestResult>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let before_all = run_hooks(ctx, Hook::BeforeAll, &plan.before_all, col_id, exports_map).await?;
+    let before_all_passed = hooks_passed(&before_all);
+    for step_result in before_all {
+        finish_step(ctx, results, step_result);
+    }
+    if !before_all_passed {
+        if ctx.should_log {
+            log::error!(target:"testkit", "A before_all hook failed, skipping the steps");
+        }
+        for (i, test_item) in plan.steps.iter().enumerate() {
+            let step_result = RequestResult {
+                step_name: test_item.title.clone(),
+                step_index: i as u32,
+                step_log: "A before_all hook failed, skipping".to_string(),
+                skipped: true,
+                ..Default::default()
+            };
+            finish_step(ctx, results, step_result);
+        }
+        return Ok(());
+    }
 
-        if test_item.request.http_version.is_some() {
-            let version = test_item.request.http_version.clone().unwrap();
-            if version == "http-2" {
-                client = client.http2_prior_knowledge();
+    let selected_steps = select_steps(ctx, &plan.steps);
+
+    for (i, test_item) in plan.steps.iter().enumerate() {
+        if !selected_steps[i] {
+            let step_result = RequestResult {
+                step_name: test_item.title.clone(),
+                step_index: i as u32,
+                step_log: "Step not selected by --tag, --exclude-tag or --grep, skipping"
+                    .to_string(),
+                skipped: true,
+                ..Default::default()
+            };
+            finish_step(ctx, results, step_result);
+            continue;
+        }
+        let before_each = run_hooks(
+            ctx,
+            Hook::BeforeEach,
+            &plan.before_each,
+            col_id,
+            exports_map,
+        )
+        .await?;
+        let before_each_passed = hooks_passed(&before_each);
+        for step_result in before_each {
+            finish_step(ctx, results, step_result);
+        }
+        if before_each_passed {
+            let step_results = run_item(ctx, test_item, i as u32, col_id, exports_map).await?;
+            for step_result in step_results {
+                finish_step(ctx, results, step_result);
             }
+        } else {
+            let step_result = RequestResult {
+                step_name: test_item.title.clone(),
+                step_index: i as u32,
+                step_log: "A before_each hook failed, skipping".to_string(),
+                skipped: true,
+                ..Default::default()
+            };
+            if ctx.should_log {
+                log::error!(target:"testkit", "{}", step_result.step_log);
+            }
+            finish_step(ctx, results, step_result);
         }
-        if test_item.request.timeout.is_some() {
-            let timeout = test_item.request.timeout.clone().unwrap();
-            client = client.timeout(Duration::from_secs(timeout));
+        let after_each =
+            run_hooks(ctx, Hook::AfterEach, &plan.after_each, col_id, exports_map).await?;
+        for step_result in after_each {
+            finish_step(ctx, results, step_result);
         }
-        // if test_item.request.follow_redirects.is_some() {
-        //     let follow_redirects = test_item.request.follow_redirects.clone().unwrap();
-        //     client = client.redirect(follow_redirects);
-        // }
-        if test_item.request.ignore_ssl_errors.unwrap_or(false) {
-            client = client.danger_accept_invalid_certs(true);
+    }
+    Ok(())
+}
+
+// run_hooks runs the steps of a hook, sharing the exports with the steps of the plan. Every
+// step of the hook runs, even when an earlier one fails.
+pub async fn run_hooks(
+    ctx: &TestContext,
+    hook: Hook,
+    hook_items: &[TestItem],
+    col_id: Option<&String>,
+    exports_map: &mut HashMap<String, Value>,
+) -> Result<Vec<RequestResult>, Box<dyn std::error::Error>> {
+    let mut results = Vec::new();
+    for (i, test_item) in hook_items.iter().enumerate() {
+        let title = test_item
+            .title
+            .clone()
+            .unwrap_or_else(|| format!("step {}", i));
+        let message = format!("Running the {} hook `{}`", hook.name(), title);
+        if ctx.should_log {
+            log::info!(target:"testkit", "{}", message);
+        }
+        for mut step_result in run_item(ctx, test_item, i as u32, col_id, exports_map).await? {
+            step_result.hook = Some(hook);
+            step_result.step_log = format!("{}\n{}", message, step_result.step_log);
+            results.push(step_result);
         }
-        let mut step_result = RequestResult {
+    }
+    Ok(results)
+}
+
+// hooks_passed tells whether none of the hook results failed or errored
+pub fn hooks_passed(results: &[RequestResult]) -> bool {
+    results
+        .iter()
+        .all(|r| matches!(r.status(), StepStatus::Passed | StepStatus::Skipped))
+}
+
+// run_item runs a step, once per row of its `for_each` data set when it has one. The columns
+// of a row, and the `with` params of the include a step comes from, are set as variables for
+// the step only, while the step's exports are kept. With several rows, later steps see the
+// exports of the last one.
+pub async fn run_item(
+    ctx: &TestContext,
+    test_item: &TestItem,
+    step_index: u32,
+    col_id: Option<&String>,
+    exports_map: &mut HashMap<String, Value>,
+) -> Result<Vec<RequestResult>, Box<dyn std::error::Error>> {
+    if test_item.for_each.is_none() && test_item.include.is_none() {
+        return Ok(vec![
+            run_step(ctx, test_item, step_index, col_id, exports_map).await?,
+        ]);
+    }
+    let mut ctx = ctx.clone();
+    let mut params = HashMap::new();
+    // Errors of included steps point at the file they were included from
+    if let Some(include) = &test_item.include {
+        ctx.file = include.file.clone();
+        ctx.file_source = include.source.to_string();
+        params = include.with.clone();
+    }
+    let rows = match &test_item.for_each {
+        Some(for_each) => match data::rows(for_each, &ctx.file) {
+            Ok(rows) => rows.into_iter().map(Some).collect(),
+            Err(err) => {
+                let error_message = format!("Error reading the for_each data set: {}", err);
+                if ctx.should_log {
+                    log::error!(target:"testkit", "{}", error_message)
+                }
+                return Ok(vec![RequestResult {
+                    step_name: test_item.title.clone(),
+                    step_index,
+                    step_log: error_message.clone(),
+                    step_error: Some(error_message),
+                    ..Default::default()
+                }]);
+            }
+        },
+        None => vec![None],
+    };
+    if rows.is_empty() {
+        return Ok(vec![RequestResult {
             step_name: test_item.title.clone(),
-            step_index: i as u32,
+            step_index,
+            step_log: "The for_each data set is empty, skipping".to_string(),
+            skipped: true,
             ..Default::default()
-        };
+        }]);
+    }
 
-        if let Ok(client) = client.build() {
-            let mut ctx = ctx.clone();
-            if test_item.request.disabled.unwrap_or(false) {
-                step_result.step_log = "Step disabled, skipping".to_string();
-                for _ in test_item.asserts.iter() {
-                    step_result.assert_results.push(Ok(true));
-                }
-                results.push(step_result);
-                continue;
+    let title = test_item
+        .title
+        .clone()
+        .unwrap_or_else(|| format!("step {}", step_index));
+    let mut results = Vec::new();
+    for (row_index, row) in rows.into_iter().enumerate() {
+        let mut item = test_item.clone();
+        let mut step_exports = exports_map.clone();
+        step_exports.extend(params.clone());
+        if let Some(row) = row {
+            item.title = Some(format!("{} [row {}]", title, row_index));
+            step_exports.extend(row);
+        }
+        let step_result = run_step(&ctx, &item, step_index, col_id, &mut step_exports).await?;
+        for key in test_item.exports.iter().flat_map(|e| e.keys()) {
+            if let Some(value) = step_exports.get(key) {
+                exports_map.insert(key.clone(), value.clone());
             }
-            ctx.step = test_item.title.clone();
-            ctx.step_index = i as u32;
-            let url;
-            let method;
-
-            let mut request_builder = match &test_item.request.http_method {
-                HttpMethod::GET(u) => {
-                    url = format_url(&ctx, u, &exports_map);
-                    method = "GET".to_string();
-                    client.get(url.clone())
-                }
-                HttpMethod::POST(u) => {
-                    url = format_url(&ctx, u, &exports_map);
-                    method = "POST".to_string();
-                    client.post(url.clone())
-                }
-                HttpMethod::PUT(u) => {
-                    url = format_url(&ctx, u, &exports_map);
-                    method = "PUT".to_string();
-                    client.put(url.clone())
-                }
-                HttpMethod::DELETE(u) => {
-                    url = format_url(&ctx, u, &exports_map);
-                    method = "DELETE".to_string();
-                    client.delete(url.clone())
+        }
+        results.push(step_result);
+    }
+    Ok(results)
+}
+
+// run_step sends the request of a single step against the exports captured so far,
+// evaluates its assertions and adds the step's exports to exports_map. A request that can't be
+// built errors the step, so the rest of the plan and its after_all hooks still run and get
+// reported.
+pub async fn run_step(
+    ctx: &TestContext,
+    test_item: &TestItem,
+    step_index: u32,
+    col_id: Option<&String>,
+    exports_map: &mut HashMap<String, Value>,
+) -> Result<RequestResult, Box<dyn std::error::Error>> {
+    let step_start = Instant::now();
+    let started_at = chrono::Utc::now().to_rfc3339();
+    let mut step_result = match send_step(ctx, test_item, step_index, col_id, exports_map).await {
+        Ok(step_result) => step_result,
+        Err(err) => {
+            let error_message = format!("Error building request: {}", err);
+            if ctx.should_log {
+                log::error!(target:"testkit", "{}", error_message)
+            }
+            RequestResult {
+                step_name: test_item.title.clone(),
+                step_index,
+                step_log: error_message.clone(),
+                step_error: Some(error_message),
+                ..Default::default()
+            }
+        }
+    };
+    step_result.timings.started_at = Some(started_at);
+    step_result.timings.total_ms = elapsed_ms(step_start);
+    Ok(step_result)
+}
+
+async fn send_step(
+    ctx: &TestContext,
+    test_item: &TestItem,
+    step_index: u32,
+    col_id: Option<&String>,
+    exports_map: &mut HashMap<String, Value>,
+) -> Result<RequestResult, Box<dyn std::error::Error>> {
+    let should_log = ctx.should_log;
+    let mut client = reqwest::Client::builder().connection_verbose(true);
+
+    if test_item.request.http_version.is_some() {
+        let version = test_item.request.http_version.clone().unwrap();
+        if version == "http-2" {
+            client = client.http2_prior_knowledge();
+        }
+    }
+    if test_item.request.timeout.is_some() {
+        let timeout = test_item.request.timeout.clone().unwrap();
+        client = client.timeout(Duration::from_secs(timeout));
+    }
+    let redirects = Arc::new(Mutex::new(Vec::new()));
+    client = client.redirect(redirect_policy(&test_item.request, redirects.clone()));
+    if test_item.request.ignore_ssl_errors.unwrap_or(false) {
+        client = client.danger_accept_invalid_certs(true);
+    }
+    let mut step_result = RequestResult {
+        step_name: test_item.title.clone(),
+        step_index,
+        ..Default::default()
+    };
+
+    if let Ok(client) = client.build() {
+        let mut ctx = ctx.clone();
+        if test_item.request.disabled.unwrap_or(false) {
+            step_result.step_log = "Step disabled, skipping".to_string();
+            step_result.skipped = true;
+            return Ok(step_result);
+        }
+        ctx.step = test_item.title.clone();
+        ctx.step_index = step_index;
+        match skip_reason(&ctx, test_item, exports_map) {
+            Ok(None) => {}
+            Ok(Some(reason)) => {
+                step_result.step_log = format!("{}, skipping", reason);
+                step_result.skipped = true;
+                if should_log {
+                    log::info!(target:"testkit", "{}", step_result.step_log)
                 }
-                HttpMethod::PATCH(u) => {
-                    url = format_url(&ctx, u, &exports_map);
-                    method = "PATCH".to_string();
-                    client.patch(url.clone())
+                return Ok(step_result);
+            }
+            Err(err) => {
+                let diag: Report = err.into();
+                let report = report_text(&diag);
+                step_result.step_log = report.clone();
+                step_result.step_error = Some(report);
+                if should_log {
+                    log::error!(target:"testkit", "{}", report_error(diag))
                 }
-                HttpMethod::HEAD(u) => {
-                    url = format_url(&ctx, u, &exports_map);
-                    method = "HEAD".to_string();
-                    client.head(url.clone())
+                return Ok(step_result);
+            }
+        }
+        let mut request_config = test_item.request.clone();
+        if let Some(col) = col_id {
+            let mut headers = request_config.headers.clone().unwrap_or_default();
+            headers.insert("X-Testkit-Collection-ID".into(), col.clone());
+            request_config.headers = Some(headers);
+        }
+
+        let mut attempt = 1;
+        let response = loop {
+            let request_builder = build_request(
+                &ctx,
+                &client,
+                test_item,
+                col_id,
+                exports_map,
+                &mut step_result,
+            )?;
+            let response = match request_builder.build() {
+                Ok(request) => {
+                    step_result.resolved_request = Some(ResolvedRequest::from_request(&request));
+                    redirects.lock().unwrap().clear();
+                    let send_start = Instant::now();
+                    let response = client.execute(request).await;
+                    step_result.timings.response_ms = Some(elapsed_ms(send_start));
+                    match response {
+                        Ok(response) => {
+                            let (mut resp, headers) =
+                                read_response(response, &mut step_result.timings).await;
+                            resp.redirects = std::mem::take(&mut *redirects.lock().unwrap());
+                            Ok((resp, headers))
+                        }
+                        Err(err) => Err(err),
+                    }
                 }
+                Err(err) => Err(err),
+            };
+            let Some(retry) = &test_item.retry else {
+                break response;
             };
 
-            let request_line = format!(
-                "{} {} ⬅ {}/{}",
-                method,
-                url,
-                ctx.plan.clone().unwrap_or("_plan".into()),
-                ctx.step.clone().unwrap_or(ctx.step_index.to_string())
+            let mut result = Attempt {
+                response_ms: step_result.timings.response_ms,
+                ..Default::default()
+            };
+            match &response {
+                Ok((resp, _)) => {
+                    result.status = Some(resp.status);
+                    let context = serde_json::json!(RequestAndResponse {
+                        req: request_config.clone(),
+                        resp: resp.clone(),
+                    });
+                    match retry_done(&ctx, test_item, retry, context, exports_map).await {
+                        Ok(done) => result.done = done,
+                        Err(err) => result.error = Some(err),
+                    }
+                }
+                Err(err) => result.error = Some(format!("Error sending request: {}", err)),
+            }
+            let done = result.done;
+            let mut message = format!(
+                "⏳ Attempt {} of {}: {}",
+                attempt,
+                retry.attempts(),
+                match (&result.status, &result.error) {
+                    (_, Some(err)) => err.clone(),
+                    (Some(status), None) => format!("status {}", status),
+                    (None, None) => String::new(),
+                }
             );
-            step_result.step_log.push_str(&request_line);
-            step_result.step_log.push_str("\n");
+            step_result.attempts.push(result);
+            if !done && attempt < retry.attempts() {
+                let what = if retry.until.is_some() {
+                    "`until` not met"
+                } else {
+                    "step failed"
+                };
+                message.push_str(&format!(
+                    ", {}, retrying in {:?}",
+                    what,
+                    retry.delay(attempt)
+                ));
+            }
+            step_result.step_log.push_str(&message);
+            step_result.step_log.push('\n');
             if should_log {
-                log::info!(target:"testkit", "");
-                log::info!(target:"testkit", "{}", request_line.to_string());
+                log::info!(target:"testkit", "{}", message)
             }
-            request_builder = request_builder.header("X-Testkit-Run", "true");
-
-            if let Some(v) = test_item.request.params.clone() {
-                let mut params = vec![];
-                for (name, value) in v {
-                    params.push((
-                        replace_vars(name.as_str(), &exports_map),
-                        replace_vars(value.as_str(), &exports_map),
-                    ));
-                }
-                request_builder = request_builder.query(&params);
+            if done || attempt >= retry.attempts() {
+                break response;
             }
+            tokio::time::sleep(retry.delay(attempt)).await;
+            attempt += 1;
+        };
 
-            if let Some(col) = &col_id {
-                request_builder = request_builder.header("X-Testkit-Collection-ID", col);
+        match response {
+            Err(err) => {
+                let mut error_message = format!("Error sending request: {}", err);
+                // reqwest keeps why a redirect couldn't be followed in the source
+                if let Some(source) = err
+                    .is_redirect()
+                    .then(|| std::error::Error::source(&err))
+                    .flatten()
+                {
+                    error_message.push_str(&format!(", {}", source));
+                }
+                step_result.step_log.push_str(&error_message);
+                step_result.step_log.push_str("\n");
+                if should_log {
+                    log::error!(target:"testkit","{}", error_message)
+                }
+                step_result.step_error = Some(error_message);
             }
+            Ok((resp, header_hashmap)) => {
+                let status_code = resp.status;
+                let assert_object = RequestAndResponse {
+                    req: request_config,
+                    resp,
+                };
+                step_result.request = assert_object.clone();
+
+                let assert_context: Value = serde_json::json!(&assert_object);
+                if test_item.dump.unwrap_or(false) {
+                    let dump_message = format!(
+                        "💡 DUMP jsonpath request response context:\n {}",
+                        colored_json::to_colored_json_auto(&assert_context)
+                            .unwrap_or(assert_context.to_owned().to_string())
+                    );
+                    step_result.step_log.push_str(&dump_message);
+                    step_result.step_log.push_str("\n");
+                    if should_log {
+                        log::info!(target:"testkit","{}", dump_message)
+                    }
+                }
+                let assertions = check_assertions(
+                    ctx.clone(),
+                    &(test_item.asserts.clone().unwrap_or(vec![])),
+                    assert_context,
+                    &exports_map,
+                    &mut step_result.step_log,
+                )
+                .await;
+                let mut assertions = assertions;
+                if let Some(until) = test_item.retry.as_ref().and_then(|r| r.until.as_ref()) {
+                    // A poll that never finished fails the step, even when it has no asserts
+                    if !step_result.attempts.last().is_some_and(|a| a.done) {
+                        let log_val = format!("❌ {: <10}  ⮕   {} ", "UNTIL ", until);
+                        step_result.step_log.push_str(&log_val);
+                        step_result.step_log.push('\n');
+                        if should_log {
+                            log::error!(target:"testkit","{}", log_val);
+                        }
+                        assertions.push(AssertionResult {
+                            kind: "until".to_string(),
+                            expr: until.clone(),
+                            ..Default::default()
+                        });
+                    }
+                }
 
-            if let Some(headers) = &test_item.request.headers {
-                for (name, value) in headers {
-                    let mut value = value.clone();
-                    for env_var in get_env_variable_paths(&value) {
-                        match get_env_variable(&env_var) {
-                            Ok(val) => value = value.replace(&env_var, &val),
-                            Err(err) => {
-                                let error_message = format!(
-                                    "Error getting environment variable {}: {}",
-                                    env_var, err
+                if let Some(exports) = &test_item.exports {
+                    for (key, value) in exports.into_iter() {
+                        if ctx.vars.contains_key(key) {
+                            let message = format!(
+                                "Not exporting `{}`, as it is set with --var or --vars-file",
+                                key
+                            );
+                            step_result.step_log.push_str(&message);
+                            step_result.step_log.push_str("\n");
+                            if should_log {
+                                log::info!(target:"testkit","{}", message)
+                            }
+                            continue;
+                        }
+                        if value.starts_with("$.res.header.") {
+                            let header = value.replace("$.res.header.", "");
+                            let header_val = header_hashmap.get(&header);
+                            if let Some(header_val) = header_val {
+                                exports_map.insert(
+                                    format!("{}", key.to_string()),
+                                    Value::String(header_val.join("")),
                                 );
+                            }
+                            continue;
+                        }
+                        if value.starts_with("$.res.status.") {
+                            exports_map.insert(
+                                format!("{}", key.to_string()),
+                                Value::Number(status_code.clone().into()),
+                            );
+                            continue;
+                        }
+                        let json_bod = &serde_json::json!(assert_object);
+                        let export = select(json_bod, &value);
+                        match export {
+                            Ok(v) => {
+                                if let Some(evaled) = v.first() {
+                                    exports_map.insert(
+                                        format!("{}", key.to_string()),
+                                        evaled.clone().clone(),
+                                    );
+                                }
+                            }
+                            Err(err) => {
+                                let error_message = format!("Error getting export value: {}", err);
                                 step_result.step_log.push_str(&error_message);
                                 step_result.step_log.push_str("\n");
                                 if should_log {
@@ -365,181 +1388,342 @@ pub async fn base_request(
                             }
                         }
                     }
+                }
+                step_result.assertions = assertions;
+            }
+        }
+    } else {
+        step_result.step_log = "Error building request client".to_string();
+        step_result.step_error = Some(step_result.step_log.clone());
+    }
+    if matches!(
+        step_result.status(),
+        StepStatus::Failed | StepStatus::Errored
+    ) {
+        if let Some(request) = &step_result.resolved_request {
+            let message = format!(
+                "🔁 Reproduce with curl:\n{}",
+                request.to_curl(&test_item.request)
+            );
+            step_result.step_log.push_str(&message);
+            step_result.step_log.push('\n');
+            if should_log {
+                log::info!(target:"testkit", "{}", message)
+            }
+        }
+    }
+    Ok(step_result)
+}
+
+// resolve_request builds the request of a step without sending it, as it would be sent with
+// the given exports.
+pub fn resolve_request(
+    test_item: &TestItem,
+    exports_map: &HashMap<String, Value>,
+) -> Result<ResolvedRequest, Box<dyn std::error::Error>> {
+    let mut step_result = RequestResult::default();
+    let request = build_request(
+        &TestContext::default(),
+        &reqwest::Client::new(),
+        test_item,
+        None,
+        exports_map,
+        &mut step_result,
+    )?
+    .build()?;
+    Ok(ResolvedRequest::from_request(&request))
+}
+
+// build_request prepares the request of a step, with env variables and exports substituted
+// into its url, params, headers and body. Substitution errors are written to the step log.
+fn build_request(
+    ctx: &TestContext,
+    client: &reqwest::Client,
+    test_item: &TestItem,
+    col_id: Option<&String>,
+    exports_map: &HashMap<String, Value>,
+    step_result: &mut RequestResult,
+) -> Result<reqwest::RequestBuilder, Box<dyn std::error::Error>> {
+    let should_log = ctx.should_log;
+
+    let (method, u) = test_item.request.http_method.method_and_url();
+    let url = format_url(ctx, u, exports_map);
+    let mut request_builder =
+        client.request(reqwest::Method::from_bytes(method.as_bytes())?, url.clone());
+
+    let request_line = format!(
+        "{} {} ⬅ {}/{}",
+        method,
+        url,
+        ctx.plan.clone().unwrap_or("_plan".into()),
+        ctx.step.clone().unwrap_or(ctx.step_index.to_string())
+    );
+    step_result.step_log.push_str(&request_line);
+    step_result.step_log.push_str("\n");
+    if should_log {
+        log::info!(target:"testkit", "");
+        log::info!(target:"testkit", "{}", request_line.to_string());
+    }
+    request_builder = request_builder.header("X-Testkit-Run", "true");
+
+    if let Some(v) = test_item.request.params.clone() {
+        let mut params = vec![];
+        for (name, value) in v {
+            params.push((
+                replace_vars(name.as_str(), exports_map),
+                replace_vars(value.as_str(), exports_map),
+            ));
+        }
+        request_builder = request_builder.query(&params);
+    }
 
-                    for export_var in get_vars(&value) {
-                        match exports_map
-                            .clone()
-                            .get(&export_var.replace("{{", "").replace("}}", ""))
-                        {
-                            Some(val) => value = value.replace(&export_var, &val.to_string()),
-                            None => {
-                                let error_message =
-                                    format!("Error getting local/export variable: {}", export_var);
-                                step_result.step_log.push_str(&error_message);
-                                step_result.step_log.push_str("\n");
-                                if should_log {
-                                    log::error!(target:"testkit","{}", error_message)
-                                }
-                            }
+    if let Some(col) = col_id {
+        request_builder = request_builder.header("X-Testkit-Collection-ID", col);
+    }
+
+    if let Some(headers) = &test_item.request.headers {
+        for (name, value) in headers {
+            let mut value = value.clone();
+            for env_var in get_env_variable_paths(&value) {
+                match get_env_variable(&env_var) {
+                    Ok(val) => value = value.replace(&env_var, &val),
+                    Err(err) => {
+                        let error_message =
+                            format!("Error getting environment variable {}: {}", env_var, err);
+                        step_result.step_log.push_str(&error_message);
+                        step_result.step_log.push_str("\n");
+                        if should_log {
+                            log::error!(target:"testkit","{}", error_message)
                         }
                     }
-
-                    request_builder = request_builder.header(name, value);
                 }
             }
 
-            if let Some(json) = &test_item.request.json {
-                let js_string = match json {
-                    Value::String(s) => s.clone(),
-                    _ => json.to_string(),
-                };
-                let j_string =
-                    prepare_json_body(js_string, &exports_map, &mut step_result, should_log);
-                request_builder = request_builder.header("Content-Type", "application/json");
-                let clean_json: Result<Value, serde_json::Error> = serde_json::from_str(&j_string);
-                if let Ok(json) = &clean_json {
-                    request_builder = request_builder.json(json);
-                }
-                if let Err(err) = clean_json {
-                    let error_message = format!("Error parsing json: {}", err);
-                    step_result.step_log.push_str(&error_message);
-                    step_result.step_log.push_str("\n");
-                    if should_log {
-                        log::error!(target:"testkit","{}", error_message)
+            for export_var in get_vars(&value) {
+                match exports_map
+                    .clone()
+                    .get(&export_var.replace("{{", "").replace("}}", ""))
+                {
+                    Some(val) => value = value.replace(&export_var, &var_text(val)),
+                    None => {
+                        let error_message =
+                            format!("Error getting local/export variable: {}", export_var);
+                        step_result.step_log.push_str(&error_message);
+                        step_result.step_log.push_str("\n");
+                        if should_log {
+                            log::error!(target:"testkit","{}", error_message)
+                        }
                     }
                 }
-            } else if let Some(b) = &test_item.request.request_body {
-                let mut body = b.clone();
-                for (key, val) in body.clone().iter() {
-                    body.insert(key.clone(), replace_vars(val, &exports_map));
-                }
-                request_builder = request_builder.body(serde_json::to_string(&body)?);
-            }
-
-            let mut request_config = test_item.request.clone();
-            if let Some(col) = &col_id {
-                let mut headers = request_config.headers.clone().unwrap_or_default();
-                headers.insert("X-Testkit-Collection-ID".into(), col.clone());
-                request_config.headers = Some(headers);
             }
 
-            let response = request_builder.send().await;
+            request_builder = request_builder.header(name, value);
+        }
+    }
 
-            match response {
-                Err(err) => {
-                    let error_message = format!("Error sending request: {}", err);
-                    step_result.step_log.push_str(&error_message);
-                    step_result.step_log.push_str("\n");
-                    if should_log {
-                        log::error!(target:"testkit","{}", error_message)
-                    }
-                    step_result.step_error = Some(error_message);
+    if let Some(json) = &test_item.request.json {
+        let js_string = match json {
+            Value::String(s) => s.clone(),
+            _ => json.to_string(),
+        };
+        let j_string = prepare_json_body(js_string, exports_map, step_result, should_log);
+        request_builder = request_builder.header("Content-Type", "application/json");
+        let clean_json: Result<Value, serde_json::Error> = serde_json::from_str(&j_string);
+        if let Ok(json) = &clean_json {
+            request_builder = request_builder.json(json);
+        }
+        if let Err(err) = clean_json {
+            let error_message = format!("Error parsing json: {}", err);
+            step_result.step_log.push_str(&error_message);
+            step_result.step_log.push_str("\n");
+            if should_log {
+                log::error!(target:"testkit","{}", error_message)
+            }
+        }
+    } else if let Some(b) = &test_item.request.request_body {
+        let mut body = b.clone();
+        for (key, val) in body.clone().iter() {
+            body.insert(key.clone(), replace_vars(val, exports_map));
+        }
+        request_builder = request_builder.body(serde_json::to_string(&body)?);
+    } else if let Some(raw) = &test_item.request.raw {
+        request_builder = request_builder.body(replace_vars(raw, exports_map));
+    }
+    Ok(request_builder)
+}
 
-                    results.push(step_result);
-                }
-                Ok(response) => {
-                    let status_code = response.status().as_u16();
-                    let header_hashmap = header_map_to_hashmap(response.headers());
-
-                    let raw_body_res = response.text().await;
-                    let raw_body = raw_body_res.unwrap_or("{}".to_string());
-                    let json_body_res = serde_json::from_str(&raw_body);
-                    let json_body = json_body_res.unwrap_or(Value::Object(serde_json::Map::new()));
-
-                    let assert_object = RequestAndResponse {
-                        req: request_config,
-                        resp: ResponseObject {
-                            status: status_code,
-                            headers: serde_json::json!(header_hashmap),
-                            json: json_body.clone(),
-                            raw: raw_body,
-                        },
-                    };
-                    step_result.request = assert_object.clone();
-
-                    let assert_context: Value = serde_json::json!(&assert_object);
-                    if test_item.dump.unwrap_or(false) {
-                        let dump_message = format!(
-                            "💡 DUMP jsonpath request response context:\n {}",
-                            colored_json::to_colored_json_auto(&assert_context)
-                                .unwrap_or(assert_context.to_owned().to_string())
+// select_steps applies the context's step filter to a plan. Steps that export a variable
+// which a selected step references are selected as well, so the selected steps still have
+// the values they depend on.
+fn select_steps(ctx: &TestContext, test_items: &[TestItem]) -> Vec<bool> {
+    let mut selected: Vec<bool> = test_items.iter().map(|t| ctx.filter.matches(t)).collect();
+    // Walking backwards lets dependencies of dependencies get selected in the same pass
+    for i in (0..test_items.len()).rev() {
+        if !selected[i] {
+            continue;
+        }
+        let step_source = serde_json::to_string(&test_items[i]).unwrap_or_default();
+        for var in get_vars(&step_source) {
+            let name = var.replace("{{", "").replace("}}", "");
+            let exporter = test_items[..i].iter().rposition(|t| {
+                t.exports
+                    .as_ref()
+                    .is_some_and(|exports| exports.contains_key(&name))
+            });
+            if let Some(j) = exporter {
+                if !selected[j] {
+                    selected[j] = true;
+                    if ctx.should_log {
+                        log::warn!(target:"testkit",
+                            "Running step {} ({}) even though it wasn't selected, because step {} ({}) uses its export {}",
+                            j,
+                            test_items[j].title.clone().unwrap_or_default(),
+                            i,
+                            test_items[i].title.clone().unwrap_or_default(),
+                            var
                         );
-                        step_result.step_log.push_str(&dump_message);
-                        step_result.step_log.push_str("\n");
-                        if should_log {
-                            log::info!(target:"testkit","{}", dump_message)
-                        }
-                    }
-                    let assert_results = check_assertions(
-                        ctx,
-                        &(test_item.asserts.clone().unwrap_or(vec![])),
-                        assert_context,
-                        &exports_map,
-                        &mut step_result.step_log,
-                    )
-                    .await;
-
-                    if let Some(exports) = &test_item.exports {
-                        for (key, value) in exports.into_iter() {
-                            if value.starts_with("$.res.header.") {
-                                let header = value.replace("$.res.header.", "");
-                                let header_val = header_hashmap.get(&header);
-                                if let Some(header_val) = header_val {
-                                    exports_map.insert(
-                                        format!("{}", key.to_string()),
-                                        Value::String(header_val.join("")),
-                                    );
-                                }
-                                continue;
-                            }
-                            if value.starts_with("$.res.status.") {
-                                exports_map.insert(
-                                    format!("{}", key.to_string()),
-                                    Value::Number(status_code.clone().into()),
-                                );
-                                continue;
-                            }
-                            let json_bod = &serde_json::json!(assert_object);
-                            let export = select(json_bod, &value);
-                            match export {
-                                Ok(v) => {
-                                    if let Some(evaled) = v.first() {
-                                        exports_map.insert(
-                                            format!("{}", key.to_string()),
-                                            evaled.clone().clone(),
-                                        );
-                                    }
-                                }
-                                Err(err) => {
-                                    let error_message =
-                                        format!("Error getting export value: {}", err);
-                                    step_result.step_log.push_str(&error_message);
-                                    step_result.step_log.push_str("\n");
-                                    if should_log {
-                                        log::error!(target:"testkit","{}", error_message)
-                                    }
-                                }
-                            }
-                        }
                     }
-                    step_result.assert_results = assert_results;
-                    results.push(step_result);
                 }
             }
-        } else {
-            step_result.step_log = "Error building request client".to_string();
-            for _ in test_item.asserts.iter() {
-                step_result.assert_results.push(Err(AssertionError {
-                    advice: Some("request failed to initialize".to_string()),
-                    src: NamedSource::new("", "".to_string()),
-                    bad_bit: (0, 0).into(),
-                }));
+        }
+    }
+    selected
+}
+
+fn finish_step(ctx: &TestContext, results: &mut Vec<RequestResult>, step_result: RequestResult) {
+    if let Some(on_step) = &ctx.on_step {
+        on_step(&ctx.file, &step_result);
+    }
+    results.push(step_result);
+}
+
+// skip_reason evaluates the `if` and `skip_if` conditions of a step, and returns why the step
+// is skipped, if it is.
+fn skip_reason(
+    ctx: &TestContext,
+    test_item: &TestItem,
+    exports_map: &HashMap<String, Value>,
+) -> Result<Option<String>, AssertionError> {
+    if let Some(expr) = &test_item.run_if {
+        let (run, eval_expr) = check_condition(ctx, expr, exports_map)?;
+        if !run {
+            return Ok(Some(format!(
+                "Step skipped, `if: {}` is false ({})",
+                expr, eval_expr
+            )));
+        }
+    }
+    if let Some(expr) = &test_item.skip_if {
+        let (skip, eval_expr) = check_condition(ctx, expr, exports_map)?;
+        if skip {
+            return Ok(Some(format!(
+                "Step skipped, `skip_if: {}` is true ({})",
+                expr, eval_expr
+            )));
+        }
+    }
+    Ok(None)
+}
+
+// check_condition evaluates a condition like an `ok` assertion, but before there is a response.
+// Variables that no earlier step exported and env variables that aren't set are null, so
+// `{{createdId}} != null` checks whether a step exported createdId. Env variables are written
+// as strings, unless they are numbers or booleans.
+fn check_condition(
+    ctx: &TestContext,
+    expr: &str,
+    exports_map: &HashMap<String, Value>,
+) -> Result<(bool, String), AssertionError> {
+    let mut resolved = expr.to_string();
+    for var in get_vars(expr) {
+        let name = var.replace("{{", "").replace("}}", "");
+        if !exports_map.contains_key(&name) {
+            resolved = resolved.replace(&var, "null");
+        }
+    }
+    let env_regex = Regex::new(r#""?\$\.env\.([A-Za-z_][A-Za-z0-9_]*)"?"#).unwrap();
+    let resolved = env_regex.replace_all(&resolved, |caps: &regex::Captures| {
+        let quoted = caps[0].starts_with('"');
+        match env::var(&caps[1]) {
+            Ok(val)
+                if !quoted && (val.parse::<f64>().is_ok() || val == "true" || val == "false") =>
+            {
+                val
             }
-            results.push(step_result);
-            continue;
+            Ok(val) => Value::String(val).to_string(),
+            Err(_) => "null".to_string(),
         }
+    });
+    evaluate_expressions::<bool>(
+        ctx.clone(),
+        &resolved.to_string(),
+        &Value::Null,
+        exports_map,
+    )
+}
+
+// read_response reads the status, headers and body of a response into the context that
+// assertions are evaluated against. The headers are also returned as is, for exports.
+async fn read_response(
+    response: reqwest::Response,
+    timings: &mut StepTimings,
+) -> (ResponseObject, HashMap<String, Vec<String>>) {
+    let status = response.status().as_u16();
+    let header_hashmap = header_map_to_hashmap(response.headers());
+
+    let body_start = Instant::now();
+    let raw_body_res = response.text().await;
+    timings.body_ms = Some(elapsed_ms(body_start));
+    let raw_body = raw_body_res.unwrap_or("{}".to_string());
+    let json_body_res = serde_json::from_str(&raw_body);
+    let json_body = json_body_res.unwrap_or(Value::Object(serde_json::Map::new()));
+    let resp = ResponseObject {
+        status,
+        headers: serde_json::json!(header_hashmap),
+        json: json_body,
+        raw: raw_body,
+        ..Default::default()
+    };
+    (resp, header_hashmap)
+}
+
+// retry_done tells whether a step with a retry block can stop being re-sent: when its `until`
+// expression holds, or when it has none, when all of its asserts pass.
+async fn retry_done(
+    ctx: &TestContext,
+    test_item: &TestItem,
+    retry: &Retry,
+    context: Value,
+    exports_map: &HashMap<String, Value>,
+) -> Result<bool, String> {
+    if let Some(until) = &retry.until {
+        return evaluate_expressions::<bool>(ctx.clone(), until, &context, exports_map)
+            .map(|(done, _)| done)
+            .map_err(|err| {
+                format!(
+                    "`until` could not be evaluated: {}",
+                    err.advice().unwrap_or_default()
+                )
+            });
     }
-    Ok(results)
+    // The asserts are evaluated again once the retries are over, and only logged then
+    let quiet = TestContext {
+        should_log: false,
+        ..ctx.clone()
+    };
+    let assertions = check_assertions(
+        quiet,
+        test_item.asserts.as_deref().unwrap_or_default(),
+        context,
+        exports_map,
+        &mut String::new(),
+    )
+    .await;
+    Ok(assertions.iter().all(|a| a.passed))
+}
+
+fn elapsed_ms(start: Instant) -> f64 {
+    start.elapsed().as_secs_f64() * 1000.0
 }
 fn header_map_to_hashmap(headers: &HeaderMap<HeaderValue>) -> HashMap<String, Vec<String>> {
     let mut header_hashmap = HashMap::new();
@@ -553,10 +1737,10 @@ fn header_map_to_hashmap(headers: &HeaderMap<HeaderValue>) -> HashMap<String, Ve
 
 // Naive implementation that might not work for all jsonpaths and might need to be changed.
 // Should add tests to check which jsonpaths would not be supported
-fn find_all_jsonpaths(input: &String) -> Vec<&str> {
+pub(crate) fn find_all_jsonpaths(input: &String) -> Vec<&str> {
     input
         .split_whitespace()
-        .filter(|x| x.starts_with("$.resp"))
+        .filter(|x| x.starts_with("$.resp") || x.starts_with("$.req."))
         .collect()
 }
 
@@ -590,12 +1774,7 @@ fn format_url(
     for export in get_vars(&url) {
         let target_var = export.clone().replace("{{", "").replace("}}", "");
         match exports_map.get(&target_var) {
-            Some(v) => match v {
-                Value::String(s) => {
-                    url = url.replace(&export, &s);
-                }
-                _ => url = url,
-            },
+            Some(v) => url = url.replace(&export, &var_text(v)),
             None => {
                 let error_message = format!("Export not found: {}", export);
                 log::error!(target:"testkit","{}", error_message)
@@ -639,7 +1818,7 @@ fn format_url(
 //     val_map
 // }
 
-fn get_vars(expr: &str) -> Vec<String> {
+pub(crate) fn get_vars(expr: &str) -> Vec<String> {
     let regex_pattern = r#"\{\{([a-zA-Z0-9_]+)\}\}"#;
     let regex = Regex::new(regex_pattern).unwrap();
     let vars: Vec<String> = regex
@@ -664,19 +1843,22 @@ fn replace_vars(expr: &str, exports_map: &HashMap<String, Value>) -> String {
     let mut result = expr.to_string();
     for var in vars {
         let target_var = var.clone().replace("{{", "").replace("}}", "");
-        match exports_map.get(&target_var) {
-            Some(v) => match v {
-                Value::String(s) => {
-                    result = result.replace(&var, &s);
-                }
-                _ => result = result,
-            },
-            None => result = result,
+        if let Some(v) = exports_map.get(&target_var) {
+            result = result.replace(&var, &var_text(v));
         }
     }
     result
 }
 
+// var_text is how an exported value is written into urls, headers and params.
+// Strings are written without their JSON quotes.
+fn var_text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        v => v.to_string(),
+    }
+}
+
 fn get_env_variable(env_key_path: &String) -> Result<String, VarError> {
     let key = env_key_path.split(".").last().unwrap_or_default();
     env::var(key)
@@ -879,10 +2061,15 @@ async fn check_assertions(
     json_body: Value,
     outputs: &HashMap<String, Value>,
     step_log: &mut String,
-) -> Vec<Result<bool, AssertionError>> {
-    let mut assert_results: Vec<Result<bool, AssertionError>> = Vec::new();
+) -> Vec<AssertionResult> {
+    let mut assert_results: Vec<AssertionResult> = Vec::new();
     let should_log = ctx.should_log;
     for assertion in asserts {
+        let mut assert_result = AssertionResult {
+            kind: assertion.kind().to_string(),
+            expr: assertion.expr().clone(),
+            ..Default::default()
+        };
         let eval_result = match assertion {
             Assert::IsOk(expr) => {
                 evaluate_expressions::<bool>(ctx.clone(), expr, &json_body, outputs)
@@ -930,13 +2117,19 @@ async fn check_assertions(
 
         match eval_result {
             Err(err) => {
-                assert_results.push(Err(err.clone()));
+                assert_result.error = Some(err.clone());
+                assert_results.push(assert_result);
+                let diag: Report = err.into();
+                step_log.push_str(&report_text(&diag));
+                step_log.push_str("\n");
                 if should_log {
-                    log::error!(target:"testkit","{}", report_error((err).into()))
+                    log::error!(target:"testkit","{}", report_error(diag))
                 }
             }
-            Ok((prefix, result, expr, _eval_expr)) => {
-                assert_results.push(Ok(result));
+            Ok((prefix, result, expr, eval_expr)) => {
+                assert_result.passed = result;
+                assert_result.eval_expr = Some(eval_expr);
+                assert_results.push(assert_result);
                 if result {
                     let log_val = format!("✅ {: <10}  ⮕   {} ", prefix, expr);
                     step_log.push_str(&log_val);
@@ -1021,7 +2214,7 @@ pub fn prepare_json_body(
 // and checks if it (contains, not contains, regex match, not regex match)
 // returns a result of the evaluation
 pub fn evaluate_funcs<T: Clone + 'static>(
-    _ctx: TestContext,
+    ctx: TestContext,
     expr: &str,
     json_body: &Value,
     assert_type: &str,
@@ -1037,6 +2230,20 @@ pub fn evaluate_funcs<T: Clone + 'static>(
     }
     let jsonpath = exprs[0];
     let target_value = replace_vars(exprs[1], outputs);
+    let regex = if assert_type == "regexMatch" || assert_type == "notRegexMatch" {
+        match Regex::new(&target_value) {
+            Ok(regex) => Some(regex),
+            Err(err) => {
+                return Err(AssertionError {
+                    advice: Some(format!("invalid regex: {}", err)),
+                    src: NamedSource::new(ctx.file, expr.to_string()),
+                    bad_bit: (exprs[0].len() + 1, exprs[1].len()).into(),
+                });
+            }
+        }
+    } else {
+        None
+    };
     match select(&json_body, &jsonpath) {
         Ok(selected_value) => {
             if let Some(selected_value) = selected_value.first() {
@@ -1066,12 +2273,12 @@ pub fn evaluate_funcs<T: Clone + 'static>(
                             }
                         }
                         if assert_type == "regexMatch" {
-                            if regex::Regex::new(&target_value).unwrap().is_match(v) {
+                            if regex.as_ref().is_some_and(|r| r.is_match(v)) {
                                 return Ok((true, expr.to_string()));
                             }
                         }
                         if assert_type == "notRegexMatch" {
-                            if !regex::Regex::new(&target_value).unwrap().is_match(v) {
+                            if !regex.as_ref().is_some_and(|r| r.is_match(v)) {
                                 return Ok((true, expr.to_string()));
                             }
                         }
@@ -1094,12 +2301,12 @@ pub fn evaluate_funcs<T: Clone + 'static>(
                 }
             }
             if assert_type == "regexMatch" {
-                if regex::Regex::new(&target_value).unwrap().is_match(jsonpath) {
+                if regex.as_ref().is_some_and(|r| r.is_match(jsonpath)) {
                     return Ok((true, expr.to_string()));
                 }
             }
             if assert_type == "notRegexMatch" {
-                if !regex::Regex::new(&target_value).unwrap().is_match(jsonpath) {
+                if !regex.as_ref().is_some_and(|r| r.is_match(jsonpath)) {
                     return Ok((true, expr.to_string()));
                 }
             }
@@ -1112,10 +2319,44 @@ pub fn evaluate_funcs<T: Clone + 'static>(
 // value. Eg `5 + 5` becomes `10`
 fn parse_expression<T: Clone + 'static>(expr: &str) -> Result<T, Box<dyn std::error::Error>> {
     let engine = Engine::new();
-    let result = engine.eval_expression::<T>(expr)?;
+    let result = engine.eval_expression::<T>(&null_to_unit(expr))?;
     Ok(result)
 }
 
+// null_to_unit rewrites `null` outside of string literals as `()`, as null is a reserved word in
+// rhai. `()` only equals itself, so `$.resp.json.deleted_at == null` works as expected.
+fn null_to_unit(expr: &str) -> String {
+    let mut out = String::with_capacity(expr.len());
+    let (mut in_string, mut escaped) = (false, false);
+    let is_ident = |c: Option<char>| c.is_some_and(|c| c.is_alphanumeric() || c == '_');
+    let mut i = 0;
+    while i < expr.len() {
+        let rest = &expr[i..];
+        let c = rest.chars().next().unwrap_or_default();
+        if in_string {
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == '"' {
+                in_string = false;
+            }
+        } else if c == '"' {
+            in_string = true;
+        } else if rest.starts_with("null")
+            && !is_ident(expr[..i].chars().next_back())
+            && !is_ident(rest[4..].chars().next())
+        {
+            out.push_str("()");
+            i += 4;
+            continue;
+        }
+        out.push(c);
+        i += c.len_utf8();
+    }
+    out
+}
+
 fn yaml_to_json(yaml_str: &str) -> Result<String, Box<dyn std::error::Error>> {
     // Parse the YAML string
     let yaml_value: serde_yaml::Value = serde_yaml::from_str(yaml_str)?;
@@ -1155,6 +2396,7 @@ mod tests {
             step: Some("step_name".into()),
             step_index: 0,
             should_log: true,
+            ..Default::default()
         };
         let resp = run_json(ctx.clone(), val.into(), None, None).await;
         assert!(resp.is_ok());
@@ -1268,6 +2510,7 @@ mod tests {
             step: Some("step_name".into()),
             step_index: 0,
             should_log: true,
+            ..Default::default()
         };
         let resp = run(ctx.clone(), yaml_str.clone()).await;
         assert!(resp.is_ok());
@@ -1298,4 +2541,543 @@ mod tests {
         m.assert_hits(2);
         log::info!("{:#?}", resp);
     }
+
+    #[tokio::test]
+    async fn test_export_substitution() {
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.method(POST).path("/login");
+            then.status(200)
+                .json_body(json!({ "token": "abc", "id": 42, "admin": true }));
+        });
+        let user = server.mock(|when, then| {
+            when.method(GET)
+                .path("/users/42")
+                .query_param("admin", "true")
+                .header("Authorization", "Bearer abc")
+                .header("X-User-Id", "42");
+            then.status(200);
+        });
+        let yaml_str = format!(
+            r#"
+- title: login
+  POST: {}
+  exports:
+    token: $.resp.json.token
+    userId: $.resp.json.id
+    admin: $.resp.json.admin
+- title: get user
+  GET: {}/{{{{userId}}}}
+  params:
+    admin: "{{{{admin}}}}"
+  headers:
+    Authorization: "Bearer {{{{token}}}}"
+    X-User-Id: "{{{{userId}}}}"
+  asserts:
+    - ok: $.resp.status == 200
+"#,
+            server.url("/login"),
+            server.url("/users")
+        );
+        let results = run(TestContext::default(), yaml_str).await.unwrap();
+        user.assert_hits(1);
+        assert_eq!(
+            results[1].status(),
+            StepStatus::Passed,
+            "{}",
+            results[1].step_log
+        );
+    }
+
+    #[tokio::test]
+    async fn test_step_filter_runs_dependencies() {
+        let server = MockServer::start();
+        let login = server.mock(|when, then| {
+            when.method(POST).path("/login");
+            then.status(200).json_body(json!({ "token": "abc" }));
+        });
+        let orders = server.mock(|when, then| {
+            when.method(GET).path("/orders").header_exists("Authorization");
+            then.status(200).json_body(json!([]));
+        });
+        let report = server.mock(|when, then| {
+            when.method(GET).path("/report");
+            then.status(200);
+        });
+        let yaml_str = format!(
+            r#"
+ - title: login
+   POST: {}
+   exports:
+     token: $.resp.json.token
+ - title: list orders
+   tags: [smoke]
+   GET: {}
+   headers:
+     Authorization: "{{{{token}}}}"
+   asserts:
+     - ok: $.resp.status == 200
+ - title: build report
+   tags: [slow]
+   GET: {}
+"#,
+            server.url("/login"),
+            server.url("/orders"),
+            server.url("/report")
+        );
+        let ctx = TestContext {
+            filter: StepFilter {
+                tags: vec!["smoke".into()],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let results = run(ctx, yaml_str).await.unwrap();
+        login.assert_hits(1);
+        orders.assert_hits(1);
+        report.assert_hits(0);
+        let statuses: Vec<StepStatus> = results.iter().map(|r| r.status()).collect();
+        assert_eq!(
+            statuses,
+            vec![StepStatus::Passed, StepStatus::Passed, StepStatus::Skipped]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_retry_until() {
+        let server = MockServer::start();
+        let running = server.mock(|when, then| {
+            when.method(GET).path("/jobs/1");
+            then.status(200).json_body(json!({ "status": "running" }));
+        });
+        let done = server.mock(|when, then| {
+            when.method(GET).path("/jobs/2");
+            then.status(200).json_body(json!({ "status": "done" }));
+        });
+        let yaml_str = format!(
+            r#"
+ - title: poll a job that never finishes
+   GET: {}
+   retry:
+     attempts: 3
+     delay_ms: 1
+     backoff: exponential
+     until: $.resp.json.status == "done"
+ - title: poll a finished job
+   GET: {}
+   retry:
+     attempts: 3
+     until: $.resp.json.status == "done"
+   asserts:
+     - ok: $.resp.status == 200
+"#,
+            server.url("/jobs/1"),
+            server.url("/jobs/2")
+        );
+        let results = run(TestContext::default(), yaml_str).await.unwrap();
+        running.assert_hits(3);
+        done.assert_hits(1);
+
+        assert_eq!(results[0].status(), StepStatus::Failed);
+        assert_eq!(results[0].attempts.len(), 3);
+        assert!(results[0].attempts.iter().all(|a| !a.done));
+        assert_eq!(results[0].assertions[0].kind, "until");
+
+        assert_eq!(results[1].status(), StepStatus::Passed);
+        assert_eq!(results[1].attempts.len(), 1);
+        assert_eq!(results[1].attempts[0].status, Some(200));
+        assert_eq!(results[1].assertions.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_conditional_steps() {
+        let server = MockServer::start();
+        let create = server.mock(|when, then| {
+            when.method(POST).path("/todos");
+            then.status(201)
+                .json_body(json!({ "id": 7, "deleted_at": null }));
+        });
+        let delete = server.mock(|when, then| {
+            when.method(DELETE).path("/todos/7");
+            then.status(204);
+        });
+        let feature = server.mock(|when, then| {
+            when.method(GET).path("/feature");
+            then.status(200);
+        });
+        let yaml_str = format!(
+            r#"
+ - title: create
+   POST: {}
+   asserts:
+     - ok: $.resp.json.deleted_at == null
+   exports:
+     createdId: $.resp.json.id
+ - title: cleanup
+   DELETE: {}
+   if: "{{{{createdId}}}} != null"
+ - title: cleanup of a todo that was never created
+   DELETE: {}
+   if: "{{{{missingId}}}} != null"
+ - title: feature
+   GET: {}
+   skip_if: $.env.TESTKIT_TEST_FEATURE != "on"
+"#,
+            server.url("/todos"),
+            server.url("/todos/{{createdId}}"),
+            server.url("/todos/{{missingId}}"),
+            server.url("/feature")
+        );
+        let results = run(TestContext::default(), yaml_str).await.unwrap();
+        create.assert_hits(1);
+        delete.assert_hits(1);
+        feature.assert_hits(0);
+        let statuses: Vec<StepStatus> = results.iter().map(|r| r.status()).collect();
+        assert_eq!(
+            statuses,
+            vec![
+                StepStatus::Passed,
+                StepStatus::Passed,
+                StepStatus::Skipped,
+                StepStatus::Skipped
+            ]
+        );
+        assert!(
+            results[3].step_log.contains(
+                r#"`skip_if: $.env.TESTKIT_TEST_FEATURE != "on"` is true (null != "on")"#
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_for_each() {
+        let server = MockServer::start();
+        let valid = server.mock(|when, then| {
+            when.method(POST)
+                .path("/payments")
+                .json_body(json!({ "amount": 10, "currency": "EUR" }));
+            then.status(201).json_body(json!({ "id": 1 }));
+        });
+        let invalid = server.mock(|when, then| {
+            when.method(POST)
+                .path("/payments")
+                .json_body(json!({ "amount": -1, "currency": "EUR" }));
+            then.status(400).json_body(json!({ "id": null }));
+        });
+        let yaml_str = format!(
+            r#"
+ - title: create payment
+   POST: {}
+   json: '{{"amount": {{{{amount}}}}, "currency": {{{{currency}}}}}}'
+   for_each:
+     - {{ amount: 10, currency: EUR, status: 201 }}
+     - {{ amount: -1, currency: EUR, status: 400 }}
+   asserts:
+     - ok: $.resp.status == {{{{status}}}}
+   exports:
+     paymentId: $.resp.json.id
+"#,
+            server.url("/payments")
+        );
+        let mut exports_map = HashMap::new();
+        let test_items: Vec<TestItem> = serde_yaml::from_str(&yaml_str).unwrap();
+        let results = run_item(
+            &TestContext::default(),
+            &test_items[0],
+            0,
+            None,
+            &mut exports_map,
+        )
+        .await
+        .unwrap();
+        valid.assert_hits(1);
+        invalid.assert_hits(1);
+
+        let names: Vec<_> = results.iter().map(|r| r.step_name.clone()).collect();
+        assert_eq!(
+            names,
+            vec![
+                Some("create payment [row 0]".to_string()),
+                Some("create payment [row 1]".to_string())
+            ]
+        );
+        assert!(results.iter().all(|r| r.status() == StepStatus::Passed));
+        // The columns are only set for their row, while the exports of the last row are kept
+        assert_eq!(exports_map.get("paymentId"), Some(&Value::Null));
+        assert!(!exports_map.contains_key("amount"));
+    }
+
+    #[tokio::test]
+    async fn test_hooks() {
+        let server = MockServer::start();
+        let login = server.mock(|when, then| {
+            when.method(POST).path("/login");
+            then.status(200).json_body(json!({ "token": "abc" }));
+        });
+        let create = server.mock(|when, then| {
+            when.method(POST)
+                .path("/todos")
+                .header("Authorization", "abc");
+            then.status(201).json_body(json!({ "id": 7 }));
+        });
+        let ping = server.mock(|when, then| {
+            when.method(GET).path("/ping");
+            then.status(200);
+        });
+        let delete = server.mock(|when, then| {
+            when.method(DELETE).path("/todos/7");
+            then.status(204);
+        });
+        let yaml_str = format!(
+            r#"
+before_all:
+  - title: login
+    POST: {login}
+    asserts:
+      - ok: $.resp.status == 200
+    exports:
+      token: $.resp.json.token
+before_each:
+  - GET: {ping}
+steps:
+  - title: create
+    POST: {todos}
+    headers:
+      Authorization: "{{{{token}}}}"
+    asserts:
+      - ok: $.resp.status == 200
+    exports:
+      createdId: $.resp.json.id
+  - title: create again
+    POST: {todos}
+    headers:
+      Authorization: "{{{{token}}}}"
+    asserts:
+      - ok: $.resp.status == 200
+after_all:
+  - title: cleanup
+    DELETE: {todo}
+"#,
+            login = server.url("/login"),
+            ping = server.url("/ping"),
+            todos = server.url("/todos"),
+            todo = server.url("/todos/{{createdId}}"),
+        );
+        let results = run(TestContext::default(), yaml_str.clone()).await.unwrap();
+        login.assert_hits(1);
+        ping.assert_hits(2);
+        create.assert_hits(2);
+        // The cleanup runs even though the steps failed
+        delete.assert_hits(1);
+        let steps: Vec<(Option<Hook>, StepStatus)> =
+            results.iter().map(|r| (r.hook, r.status())).collect();
+        assert_eq!(
+            steps,
+            vec![
+                (Some(Hook::BeforeAll), StepStatus::Passed),
+                (Some(Hook::BeforeEach), StepStatus::Passed),
+                (None, StepStatus::Failed),
+                (Some(Hook::BeforeEach), StepStatus::Passed),
+                (None, StepStatus::Failed),
+                (Some(Hook::AfterAll), StepStatus::Passed),
+            ]
+        );
+
+        // When a before_all hook fails, the steps are skipped and the after_all hooks still run
+        let yaml_str = yaml_str.replace("/login", "/missing");
+        let results = run(TestContext::default(), yaml_str).await.unwrap();
+        create.assert_hits(2);
+        delete.assert_hits(1);
+        let statuses: Vec<StepStatus> = results.iter().map(|r| r.status()).collect();
+        assert_eq!(
+            statuses,
+            vec![
+                StepStatus::Failed,
+                StepStatus::Skipped,
+                StepStatus::Skipped,
+                StepStatus::Passed,
+            ]
+        );
+        assert_eq!(results[1].step_log, "A before_all hook failed, skipping");
+
+        // A step whose request can't be built errors, and the after_all hooks are still reported
+        let mut plan: TestPlan = serde_yaml::from_str(&format!(
+            r#"
+steps:
+  - title: bad method
+    GET: {ping}
+after_all:
+  - title: cleanup
+    DELETE: {missing}
+    asserts:
+      - ok: $.resp.status == 204
+"#,
+            ping = server.url("/ping"),
+            missing = server.url("/todos/missing")
+        ))
+        .unwrap();
+        plan.steps[0].request.http_method = HttpMethod::Custom {
+            method: "BAD METHOD".into(),
+            url: server.url("/ping"),
+        };
+        let results = base_request(TestContext::default(), &plan, None, None)
+            .await
+            .unwrap();
+        let statuses: Vec<(Option<Hook>, StepStatus)> =
+            results.iter().map(|r| (r.hook, r.status())).collect();
+        assert_eq!(
+            statuses,
+            vec![
+                (None, StepStatus::Errored),
+                (Some(Hook::AfterAll), StepStatus::Failed),
+            ]
+        );
+        assert!(
+            results[0]
+                .step_error
+                .as_ref()
+                .unwrap()
+                .starts_with("Error building request")
+        );
+    }
+
+    #[test]
+    fn test_assert_kinds() {
+        for kind in ASSERT_KINDS {
+            let assert: Assert = serde_json::from_value(json!({ *kind: "$.resp.json" })).unwrap();
+            assert_eq!(assert.kind(), *kind);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_http_methods() {
+        let server = MockServer::start();
+        let preflight = server.mock(|when, then| {
+            when.method(OPTIONS).path("/todos");
+            then.status(204).header("Access-Control-Allow-Origin", "*");
+        });
+        // httpmock can't match custom methods, so the method is checked with an assert
+        let purge = server.mock(|when, then| {
+            when.path("/cache/todos");
+            then.status(200);
+        });
+        let yaml_str = format!(
+            r#"
+- title: preflight
+  OPTIONS: {}
+  asserts:
+    - ok: $.resp.status == 204
+    - ok: $.req.method == "OPTIONS"
+- title: purge
+  method: PURGE
+  url: {}
+  asserts:
+    - ok: $.resp.status == 200
+    - ok: $.req.method == "PURGE"
+"#,
+            server.url("/todos"),
+            server.url("/cache/todos")
+        );
+        let results = run(TestContext::default(), yaml_str.clone()).await.unwrap();
+        preflight.assert_hits(1);
+        purge.assert_hits(1);
+        for result in &results {
+            assert_eq!(result.status(), StepStatus::Passed, "{}", result.step_log);
+        }
+        assert_eq!(
+            results[1].resolved_request.as_ref().unwrap().method,
+            "PURGE"
+        );
+
+        let items: Vec<TestItem> = serde_yaml::from_str(&yaml_str).unwrap();
+        assert_eq!(
+            items[1].request.http_method,
+            HttpMethod::Custom {
+                method: "PURGE".into(),
+                url: server.url("/cache/todos")
+            }
+        );
+        let yaml = serde_yaml::to_string(&items).unwrap();
+        assert!(yaml.contains("OPTIONS: http://") && yaml.contains("method: PURGE"));
+        assert!(serde_yaml::from_str::<TestItem>("method: PURGE").is_err());
+        assert!(
+            serde_yaml::from_str::<TestItem>("GET: http://localhost\nurl: http://localhost")
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_redirects() {
+        let server = MockServer::start();
+        let login = server.mock(|when, then| {
+            when.method(GET).path("/login");
+            then.status(302).header("Location", "/authorize?client=tk");
+        });
+        let authorize = server.mock(|when, then| {
+            when.method(GET).path("/authorize");
+            then.status(303).header("Location", "/home");
+        });
+        server.mock(|when, then| {
+            when.method(GET).path("/home");
+            then.status(200).json_body(json!({"user": "ada"}));
+        });
+        let yaml_str = format!(
+            r#"
+- title: follows the redirects
+  GET: {login}
+  asserts:
+    - ok: $.resp.status == 200
+    - ok: $.resp.json.user == "ada"
+    - ok: $.resp.redirects[0].status == 302
+    - ok: $.resp.redirects[1].status == 303
+- title: stops at the redirect
+  GET: {login}
+  follow_redirects: false
+  asserts:
+    - ok: $.resp.status == 302
+    - ok: $.resp.headers.location[0] == "/authorize?client=tk"
+- title: too many redirects
+  GET: {login}
+  max_redirects: 1
+"#,
+            login = server.url("/login")
+        );
+        let results = run(TestContext::default(), yaml_str).await.unwrap();
+        login.assert_hits(3);
+        authorize.assert_hits(2);
+        assert_eq!(
+            results[0].status(),
+            StepStatus::Passed,
+            "{}",
+            results[0].step_log
+        );
+        assert_eq!(
+            results[0].request.resp.redirects,
+            vec![
+                Redirect {
+                    url: server.url("/login"),
+                    status: 302
+                },
+                Redirect {
+                    url: server.url("/authorize?client=tk"),
+                    status: 303
+                },
+            ]
+        );
+        assert_eq!(
+            results[1].status(),
+            StepStatus::Passed,
+            "{}",
+            results[1].step_log
+        );
+        assert!(results[1].request.resp.redirects.is_empty());
+        assert_eq!(results[2].status(), StepStatus::Errored);
+        assert!(
+            results[2]
+                .step_error
+                .as_ref()
+                .unwrap()
+                .contains("more than 1 redirects")
+        );
+    }
 }
@@ -0,0 +1,87 @@
+use crate::base_request::ForEach;
+use serde_json::{Map, Value};
+use std::{error::Error, fs, path::Path};
+
+// Row is one iteration of a `for_each` step. Its columns are set as `{{column}}` variables.
+pub type Row = Map<String, Value>;
+
+// rows returns the data set of a `for_each` step. Files are read relative to the test file.
+pub fn rows(for_each: &ForEach, test_file: &str) -> Result<Vec<Row>, Box<dyn Error>> {
+    let file = match for_each {
+        ForEach::Rows(rows) => return Ok(rows.clone()),
+        ForEach::File(file) => file,
+    };
+    let path = Path::new(test_file)
+        .parent()
+        .unwrap_or(Path::new(""))
+        .join(file);
+    let content = fs::read_to_string(&path)
+        .map_err(|err| format!("error reading {}: {}", path.display(), err))?;
+    let extension = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    match extension.as_str() {
+        "csv" => from_csv(&content),
+        // YAML is a superset of JSON, so both are read the same way
+        "json" | "yaml" | "yml" => serde_yaml::from_str(&content).map_err(|err| {
+            format!("{}: expected a list of objects: {}", path.display(), err).into()
+        }),
+        _ => Err(format!("{}: expected a .csv, .json or .yaml file", path.display()).into()),
+    }
+}
+
+// from_csv reads a CSV file with a header row. Cells that are numbers, booleans or null are
+// read as such, so they can be sent in `json` bodies as they are. Other cells are strings.
+fn from_csv(content: &str) -> Result<Vec<Row>, Box<dyn Error>> {
+    let mut reader = csv::Reader::from_reader(content.as_bytes());
+    let headers = reader.headers()?.clone();
+    let mut rows = Vec::new();
+    for record in reader.records() {
+        let record = record?;
+        let row = headers
+            .iter()
+            .zip(record.iter())
+            .map(|(column, cell)| (column.to_string(), cell_value(cell)))
+            .collect();
+        rows.push(row);
+    }
+    Ok(rows)
+}
+
+fn cell_value(cell: &str) -> Value {
+    match serde_json::from_str::<Value>(cell) {
+        Ok(value @ (Value::Number(_) | Value::Bool(_) | Value::Null)) => value,
+        _ => Value::String(cell.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::env;
+
+    #[test]
+    fn test_rows() {
+        let dir = env::temp_dir().join("testkit-test-data");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("amounts.csv"),
+            "amount,currency,note\n0,EUR,\n-1,usd,\"a, b\"\n99999.5,EUR,null\n",
+        )
+        .unwrap();
+        let test_file = dir.join("payments.tk.yaml").to_string_lossy().to_string();
+        let rows = rows(&ForEach::File("amounts.csv".into()), &test_file).unwrap();
+        fs::remove_dir_all(&dir).ok();
+
+        assert_eq!(
+            Value::Array(rows.into_iter().map(Value::Object).collect()),
+            json!([
+                { "amount": 0, "currency": "EUR", "note": "" },
+                { "amount": -1, "currency": "usd", "note": "a, b" },
+                { "amount": 99999.5, "currency": "EUR", "note": null },
+            ])
+        );
+    }
+}
@@ -0,0 +1,159 @@
+use serde_json::Value;
+use std::{
+    collections::{HashMap, HashSet},
+    env, fs,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+// PROCESS_VARS are the names of the variables that were set before any env file was read
+static PROCESS_VARS: OnceLock<HashSet<String>> = OnceLock::new();
+
+// profile_path is the file of a named environment, eg `.env.staging` for `--env staging`
+pub fn profile_path(profile: &str) -> PathBuf {
+    PathBuf::from(format!(".env.{}", profile))
+}
+
+// load reads the .env file, and the file of the profile, if one is given, on top of it.
+// Variables that are already set in the process environment take precedence over both,
+// so `APIURL=... testkit test --env staging` still works.
+pub fn load(profile: Option<&str>) -> Result<(), String> {
+    PROCESS_VARS.get_or_init(|| {
+        env::vars_os()
+            .filter_map(|(k, _)| k.into_string().ok())
+            .collect()
+    });
+    if let Some(profile) = profile {
+        let path = profile_path(profile);
+        if !path.exists() {
+            return Err(format!(
+                "environment {:?} not found, expected its variables in {}",
+                profile,
+                path.display()
+            ));
+        }
+        // dotenv doesn't override variables that are already set, so the profile is read first
+        dotenv::from_path(&path)
+            .map_err(|err| format!("error reading {}: {}", path.display(), err))?;
+        log::info!(target:"testkit", "Using the {} environment from {}", profile, path.display());
+    }
+    dotenv::dotenv().ok();
+    Ok(())
+}
+
+// reload re-reads the .env file and the file of the profile, overriding variables that were
+// loaded from them before. dotenv::from_path doesn't override variables that are already set,
+// hence the iterator. Variables of the process environment keep winning, as they do in load.
+pub fn reload(profile: Option<&str>) {
+    let process_vars = PROCESS_VARS.get_or_init(HashSet::new);
+    let mut paths = vec![PathBuf::from(".env")];
+    paths.extend(profile.map(profile_path));
+    for path in paths {
+        // a missing .env is fine, as it is in load
+        if path.exists() {
+            reload_file(&path, process_vars);
+        }
+    }
+}
+
+#[allow(deprecated)]
+fn reload_file(path: &Path, process_vars: &HashSet<String>) {
+    match dotenv::from_path_iter(path) {
+        Ok(vars) => {
+            for (key, value) in vars.flatten() {
+                if !process_vars.contains(&key) {
+                    env::set_var(key, value);
+                }
+            }
+        }
+        Err(err) => {
+            log::error!(target:"testkit", "Error reloading {}: {}", path.display(), err)
+        }
+    }
+}
+
+// is_env_file tells whether a changed file holds variables of the active environment
+pub fn is_env_file(path: &Path, profile: Option<&str>) -> bool {
+    let Some(name) = path.file_name() else {
+        return false;
+    };
+    name == ".env" || profile.is_some_and(|p| Path::new(name) == profile_path(p))
+}
+
+// parse_var parses a `--var name=value` argument
+pub fn parse_var(arg: &str) -> Result<(String, String), String> {
+    match arg.split_once('=') {
+        Some((name, value)) if !name.trim().is_empty() => {
+            Ok((name.trim().to_string(), value.to_string()))
+        }
+        _ => Err(format!("expected name=value, got {:?}", arg)),
+    }
+}
+
+// vars builds the variables given on the command line, which seed the exports of every plan.
+// The vars file is a JSON or YAML object, so its values keep their types, while `--var`
+// values are always strings. `--var` wins over the vars file.
+pub fn vars(
+    cli_vars: &[(String, String)],
+    vars_file: Option<&Path>,
+) -> Result<HashMap<String, Value>, String> {
+    let mut vars = HashMap::new();
+    if let Some(path) = vars_file {
+        let content = fs::read_to_string(path)
+            .map_err(|err| format!("error reading {}: {}", path.display(), err))?;
+        // YAML is a superset of JSON, so both are read the same way
+        let file_vars: HashMap<String, Value> = serde_yaml::from_str(&content).map_err(|err| {
+            format!(
+                "error reading {}: expected an object of variables: {}",
+                path.display(),
+                err
+            )
+        })?;
+        vars.extend(file_vars);
+    }
+    for (name, value) in cli_vars {
+        vars.insert(name.clone(), Value::String(value.clone()));
+    }
+    Ok(vars)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_env_file() {
+        assert!(is_env_file(Path::new("./.env"), None));
+        assert!(is_env_file(Path::new("api/.env.staging"), Some("staging")));
+        assert!(!is_env_file(Path::new(".env.staging"), None));
+        assert!(!is_env_file(Path::new(".env.prod"), Some("staging")));
+    }
+
+    #[test]
+    fn test_vars() {
+        assert_eq!(
+            parse_var("query=a=b").unwrap(),
+            ("query".to_string(), "a=b".to_string())
+        );
+        assert!(parse_var("tenantId").is_err());
+        assert!(parse_var("=42").is_err());
+
+        let path = env::temp_dir().join("testkit-test-vars.yaml");
+        fs::write(&path, "tenantId: 42\nregion: eu\n").unwrap();
+        let vars = vars(&[("region".into(), "us".into())], Some(&path)).unwrap();
+        fs::remove_file(&path).ok();
+        assert_eq!(vars["tenantId"], serde_json::json!(42));
+        assert_eq!(vars["region"], serde_json::json!("us"));
+    }
+
+    #[test]
+    fn test_reload_keeps_process_vars() {
+        let path = env::temp_dir().join(format!("testkit-test-reload-{}.env", std::process::id()));
+        fs::write(&path, "TK_TEST_RELOAD_CLI=file\nTK_TEST_RELOAD_FILE=file\n").unwrap();
+        env::set_var("TK_TEST_RELOAD_CLI", "cli");
+        reload_file(&path, &HashSet::from(["TK_TEST_RELOAD_CLI".to_string()]));
+        fs::remove_file(&path).ok();
+        assert_eq!(env::var("TK_TEST_RELOAD_CLI").unwrap(), "cli");
+        assert_eq!(env::var("TK_TEST_RELOAD_FILE").unwrap(), "file");
+    }
+}
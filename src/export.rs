@@ -0,0 +1,93 @@
+use crate::{
+    base_request::{TestItem, get_vars, resolve_request},
+    include,
+};
+use serde_json::Value;
+use std::{collections::HashMap, error::Error, fs, path::Path};
+
+// export_curl renders every step of a test file as a curl command, with the before_all and
+// after_all hooks around them.
+pub fn export_curl(file: &Path) -> Result<String, Box<dyn Error>> {
+    let source = fs::read_to_string(file)?;
+    let plan = include::parse(&source, &file.to_string_lossy())?;
+    let test_items: Vec<TestItem> = plan
+        .before_all
+        .into_iter()
+        .chain(plan.steps)
+        .chain(plan.after_all)
+        .collect();
+    Ok(to_curl(&test_items))
+}
+
+// to_curl renders steps as curl commands, with env variables substituted. Exports are only known
+// once the steps that capture them run, so they are left in the commands as `{{name}}`.
+pub fn to_curl(test_items: &[TestItem]) -> String {
+    let mut commands = Vec::new();
+    for (i, test_item) in test_items.iter().enumerate() {
+        let title = test_item.title.clone().unwrap_or(format!("step {}", i));
+        let step_source = serde_json::to_string(test_item).unwrap_or_default();
+        let exports_map: HashMap<String, Value> = get_vars(&step_source)
+            .into_iter()
+            .map(|var| (var.replace("{{", "").replace("}}", ""), Value::String(var)))
+            .collect();
+        let command = match resolve_request(test_item, &exports_map) {
+            Ok(request) => request
+                .to_curl(&test_item.request)
+                .replace("%7B%7B", "{{")
+                .replace("%7D%7D", "}}"),
+            Err(err) => format!("# the request can't be built: {}", err),
+        };
+        let disabled = if test_item.request.disabled.unwrap_or(false) {
+            " (disabled)"
+        } else {
+            ""
+        };
+        commands.push(format!("# {}{}\n{}\n", title, disabled, command));
+    }
+    commands.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_curl() {
+        std::env::set_var("TK_EXPORT_HOST", "http://localhost:3000");
+        let test_items: Vec<TestItem> = serde_yaml::from_str(
+            r#"
+- title: create todo
+  POST: $.env.TK_EXPORT_HOST/todos
+  headers:
+    Authorization: Bearer {{token}}
+  json:
+    task: it's done
+  ignore_ssl_errors: true
+  max_redirects: 3
+- GET: $.env.TK_EXPORT_HOST/todos/{{id}}
+  params:
+    expand: owner
+  follow_redirects: false
+"#,
+        )
+        .unwrap();
+        let curl = to_curl(&test_items);
+        assert_eq!(
+            curl,
+            r#"# create todo
+curl -X POST 'http://localhost:3000/todos' \
+  -H 'x-testkit-run: true' \
+  -H 'authorization: Bearer {{token}}' \
+  -H 'content-type: application/json' \
+  --data-raw '{"task":"it'\''s done"}' \
+  -L \
+  --max-redirs 3 \
+  -k
+
+# step 1
+curl 'http://localhost:3000/todos/{{id}}?expand=owner' \
+  -H 'x-testkit-run: true'
+"#
+        );
+    }
+}
@@ -0,0 +1,156 @@
+pub mod curl;
+pub mod har;
+pub mod openapi;
+pub mod postman;
+
+use crate::base_request::TestItem;
+use serde_json::Value;
+use serde_yaml::{Mapping, Value as YamlValue};
+use std::{error::Error, fs, path::Path};
+
+// Keys whose values are deserialized into a HashMap, and so would come out in a random order
+const MAP_KEYS: &[&str] = &["headers", "params", "exports", "requestBody"];
+
+// Plan is a generated test plan. notes are written as comments at the top of the plan.
+#[derive(Debug, Default)]
+pub struct Plan {
+    pub name: String,
+    pub notes: Vec<String>,
+    pub steps: Vec<Step>,
+}
+
+// Step is a generated step. notes are the parts of the source that couldn't be translated,
+// and are written as comments above the step.
+#[derive(Debug, Default)]
+pub struct Step {
+    pub item: TestItem,
+    pub notes: Vec<String>,
+}
+
+impl From<TestItem> for Step {
+    fn from(item: TestItem) -> Self {
+        Step {
+            item,
+            notes: vec![],
+        }
+    }
+}
+
+// to_yaml renders a plan as a .tk.yaml file. Unset fields are left out and map keys are sorted,
+// so re-importing the same source produces the same file.
+pub fn to_yaml(plan: &Plan) -> Result<String, Box<dyn Error>> {
+    let mut out = comments(&plan.notes);
+    if !out.is_empty() {
+        out.push('\n');
+    }
+    let mut docs = Vec::new();
+    for step in &plan.steps {
+        let YamlValue::Mapping(item) = serde_yaml::to_value(&step.item)? else {
+            continue;
+        };
+        let mut cleaned = Mapping::new();
+        for (key, value) in item {
+            if value.is_null() {
+                continue;
+            }
+            let value = match (key.as_str(), value) {
+                (Some(k), YamlValue::Mapping(map)) if MAP_KEYS.contains(&k) => {
+                    let mut entries: Vec<_> = map.into_iter().collect();
+                    entries.sort_by(|a, b| a.0.as_str().cmp(&b.0.as_str()));
+                    YamlValue::Mapping(entries.into_iter().collect())
+                }
+                (_, value) => value,
+            };
+            cleaned.insert(key, value);
+        }
+        let yaml = serde_yaml::to_string(&vec![YamlValue::Mapping(cleaned)])?;
+        docs.push(comments(&step.notes) + yaml.as_str());
+    }
+    // Blank lines between steps, as hand written plans have them
+    out.push_str(&docs.join("\n"));
+    Ok(out)
+}
+
+fn comments(notes: &[String]) -> String {
+    notes
+        .iter()
+        .flat_map(|note| note.lines())
+        .map(|line| format!("# {}\n", line).replace("# \n", "#\n"))
+        .collect()
+}
+
+// write_plans writes generated plans. When output is a directory each plan is written to its
+// own file in it, otherwise all plans are written to output, or to stdout, one after another.
+pub fn write_plans(plans: &[Plan], output: Option<&Path>) -> Result<(), Box<dyn Error>> {
+    if let Some(dir) = output.filter(|o| o.is_dir()) {
+        for plan in plans {
+            let path = dir.join(format!("{}.tk.yaml", file_name(&plan.name)));
+            fs::write(&path, to_yaml(plan)?)?;
+            log::info!(target:"testkit", "Wrote {} steps to {}", plan.steps.len(), path.display());
+        }
+        return Ok(());
+    }
+
+    let mut yaml = String::new();
+    for plan in plans {
+        if plans.len() > 1 {
+            yaml.push_str(&format!("# {}\n\n", plan.name));
+        }
+        yaml.push_str(&to_yaml(plan)?);
+        yaml.push('\n');
+    }
+    let yaml = yaml.trim_end().to_string() + "\n";
+    match output {
+        Some(path) => {
+            fs::write(path, yaml)?;
+            let steps: usize = plans.iter().map(|p| p.steps.len()).sum();
+            log::info!(target:"testkit", "Wrote {} steps to {}", steps, path.display());
+        }
+        None => print!("{}", yaml),
+    }
+    Ok(())
+}
+
+// json_body parses a JSON request body. Bodies with unquoted {{variables}} only parse once the
+// variables are filled in, so those are kept as a string.
+fn json_body(body: &str) -> Value {
+    serde_json::from_str(body).unwrap_or_else(|_| Value::String(body.to_string()))
+}
+
+// to_text is the text of a JSON value, strings without their quotes and null as nothing
+fn to_text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Null => String::new(),
+        v => v.to_string(),
+    }
+}
+
+// json_path appends a key to a jsonpath, eg `$.resp.json.id`, or `$.resp.json['user-id']` for
+// keys that aren't plain identifiers
+fn json_path(path: &str, key: &str) -> String {
+    if key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        format!("{}.{}", path, key)
+    } else {
+        format!("{}['{}']", path, key)
+    }
+}
+
+// file_name turns a plan name into a file name, eg `User Accounts` into `user-accounts`
+fn file_name(name: &str) -> String {
+    let name: String = name
+        .to_lowercase()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .collect();
+    let name = name
+        .split('-')
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("-");
+    if name.is_empty() {
+        "plan".to_string()
+    } else {
+        name
+    }
+}
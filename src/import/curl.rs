@@ -0,0 +1,613 @@
+use super::{Plan, Step, json_body};
+use crate::base_request::{HttpMethod, RequestConfig, TestItem};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use reqwest::Url;
+use std::{collections::HashMap, error::Error};
+
+// The boundary -F fields are joined with, in the multipart body testkit sends as `raw`
+const BOUNDARY: &str = "testkit-form-boundary";
+
+// Options that take a value, by their long name
+const VALUE_OPTIONS: &[&str] = &[
+    "request",
+    "header",
+    "data",
+    "data-raw",
+    "data-binary",
+    "data-ascii",
+    "data-urlencode",
+    "json",
+    "form",
+    "form-string",
+    "user",
+    "oauth2-bearer",
+    "user-agent",
+    "cookie",
+    "referer",
+    "max-time",
+    "max-redirs",
+    "url",
+    "output",
+    "write-out",
+    "proxy",
+    "connect-timeout",
+    "cert",
+    "key",
+    "cacert",
+    "resolve",
+    "retry",
+    "upload-file",
+    "cookie-jar",
+];
+
+// Options that only change how curl prints or transfers, which testkit has no use for
+const IGNORED_OPTIONS: &[&str] = &[
+    "compressed",
+    "silent",
+    "show-error",
+    "verbose",
+    "include",
+    "fail",
+    "globoff",
+    "no-buffer",
+    "http1.1",
+    "http2",
+    "output",
+    "write-out",
+    "connect-timeout",
+    "retry",
+    "cookie-jar",
+];
+
+// import turns curl commands, as copied from the browser devtools or API docs, into a plan.
+// Several commands can be given one after another, separated by newlines, `;` or `&&`.
+pub fn import(commands: &str) -> Result<Plan, Box<dyn Error>> {
+    let commands = split_commands(commands)?;
+    if commands.is_empty() {
+        return Err("no curl command found".into());
+    }
+    Ok(Plan {
+        name: "curl".to_string(),
+        steps: commands
+            .iter()
+            .map(|args| from_args(args))
+            .collect::<Result<_, _>>()?,
+        ..Default::default()
+    })
+}
+
+// split_commands splits shell input into the arguments of each curl command in it, the way a
+// POSIX shell would, including `$'...'` strings and `\` line continuations.
+fn split_commands(input: &str) -> Result<Vec<Vec<String>>, String> {
+    let mut commands = Vec::new();
+    let mut args = Vec::new();
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut chars = input.chars().peekable();
+
+    let end_word = |word: &mut String, in_word: &mut bool, args: &mut Vec<String>| {
+        if *in_word {
+            args.push(std::mem::take(word));
+            *in_word = false;
+        }
+    };
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => return Err("unterminated ' quote".into()),
+                    }
+                }
+            }
+            '"' => {
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\' | '$' | '`')) => word.push(c),
+                            Some('\n') => {}
+                            Some(c) => {
+                                word.push('\\');
+                                word.push(c);
+                            }
+                            None => return Err("unterminated \" quote".into()),
+                        },
+                        Some(c) => word.push(c),
+                        None => return Err("unterminated \" quote".into()),
+                    }
+                }
+            }
+            '$' if chars.peek() == Some(&'\'') => {
+                chars.next();
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some('\\') => match chars.next() {
+                            Some('n') => word.push('\n'),
+                            Some('t') => word.push('\t'),
+                            Some('r') => word.push('\r'),
+                            Some(c @ ('\\' | '\'' | '"')) => word.push(c),
+                            Some(c @ ('x' | 'u')) => {
+                                let len = if c == 'x' { 2 } else { 4 };
+                                let mut hex = String::new();
+                                while hex.len() < len
+                                    && chars.peek().is_some_and(|h| h.is_ascii_hexdigit())
+                                {
+                                    hex.extend(chars.next());
+                                }
+                                match u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32) {
+                                    Some(decoded) => word.push(decoded),
+                                    None => {
+                                        word.push('\\');
+                                        word.push(c);
+                                        word.push_str(&hex);
+                                    }
+                                }
+                            }
+                            Some(c) => {
+                                word.push('\\');
+                                word.push(c);
+                            }
+                            None => return Err("unterminated $' quote".into()),
+                        },
+                        Some(c) => word.push(c),
+                        None => return Err("unterminated $' quote".into()),
+                    }
+                }
+            }
+            '\\' => match chars.next() {
+                Some('\n') => {}
+                Some('\r') if chars.peek() == Some(&'\n') => {
+                    chars.next();
+                }
+                Some(c) => {
+                    in_word = true;
+                    word.push(c);
+                }
+                None => {}
+            },
+            ';' | '\n' | '&' | '|' => {
+                if (c == '&' || c == '|') && chars.peek() == Some(&c) {
+                    chars.next();
+                }
+                end_word(&mut word, &mut in_word, &mut args);
+                if !args.is_empty() {
+                    commands.push(std::mem::take(&mut args));
+                }
+            }
+            c if c.is_whitespace() => end_word(&mut word, &mut in_word, &mut args),
+            c => {
+                in_word = true;
+                word.push(c);
+            }
+        }
+    }
+    end_word(&mut word, &mut in_word, &mut args);
+    if !args.is_empty() {
+        commands.push(args);
+    }
+    Ok(commands
+        .into_iter()
+        .filter(|args| args[0] == "curl" || args[0].ends_with("/curl"))
+        .collect())
+}
+
+// long_name maps curl's short options to their long names
+fn long_name(short: char) -> Option<&'static str> {
+    Some(match short {
+        'X' => "request",
+        'H' => "header",
+        'd' => "data",
+        'F' => "form",
+        'u' => "user",
+        'A' => "user-agent",
+        'b' => "cookie",
+        'e' => "referer",
+        'm' => "max-time",
+        'o' => "output",
+        'w' => "write-out",
+        'x' => "proxy",
+        'T' => "upload-file",
+        'c' => "cookie-jar",
+        'E' => "cert",
+        'G' => "get",
+        'I' => "head",
+        'k' => "insecure",
+        'L' => "location",
+        's' => "silent",
+        'S' => "show-error",
+        'v' => "verbose",
+        'i' => "include",
+        'f' => "fail",
+        'g' => "globoff",
+        'N' => "no-buffer",
+        _ => return None,
+    })
+}
+
+// options pairs every option of a command with its value, by long name. Short options can be
+// combined, eg `-sSL`, and take their value attached or as the next argument, eg `-XPOST`.
+// Arguments that aren't options are returned with the name "url".
+fn options(args: &[String]) -> Result<Vec<(String, String)>, String> {
+    let mut options = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if let Some(name) = arg.strip_prefix("--") {
+            let value = if VALUE_OPTIONS.contains(&name) {
+                args.next()
+                    .ok_or_else(|| format!("{} is missing its value", arg))?
+                    .clone()
+            } else {
+                String::new()
+            };
+            options.push((name.to_string(), value));
+        } else if arg.len() > 1 && arg.starts_with('-') {
+            for (i, short) in arg[1..].char_indices() {
+                let name = long_name(short)
+                    .map(String::from)
+                    .unwrap_or(format!("-{}", short));
+                if VALUE_OPTIONS.contains(&name.as_str()) {
+                    let attached = &arg[1 + i + short.len_utf8()..];
+                    let value = if attached.is_empty() {
+                        args.next()
+                            .ok_or_else(|| format!("-{} is missing its value", short))?
+                            .clone()
+                    } else {
+                        attached.to_string()
+                    };
+                    options.push((name, value));
+                    break;
+                }
+                options.push((name, String::new()));
+            }
+        } else {
+            options.push(("url".to_string(), arg.clone()));
+        }
+    }
+    Ok(options)
+}
+
+// from_args converts the arguments of one curl command, the first being curl itself
+fn from_args(args: &[String]) -> Result<Step, Box<dyn Error>> {
+    let mut notes = Vec::new();
+    let mut url: Option<String> = None;
+    let mut method: Option<String> = None;
+    let mut headers: Vec<(String, String)> = Vec::new();
+    let mut data: Vec<String> = Vec::new();
+    let mut form: Vec<String> = Vec::new();
+    let mut get = false;
+    let mut is_json = false;
+    let mut request = RequestConfig::default();
+
+    for (name, value) in options(&args[1..])? {
+        match name.as_str() {
+            "url" if url.is_none() => url = Some(value),
+            "url" => notes.push(format!("only the first url is imported, {} is not", value)),
+            "request" => method = Some(value.to_uppercase()),
+            "head" => method = Some("HEAD".to_string()),
+            "get" => get = true,
+            "header" => match value.split_once(':') {
+                Some((name, value)) => headers.push((name.trim().into(), value.trim().into())),
+                // `-H 'Name;'` sends an empty header
+                None if value.ends_with(';') => {
+                    headers.push((value.trim_end_matches(';').into(), String::new()))
+                }
+                None => notes.push(format!(
+                    "header {:?} has no value, it is not imported",
+                    value
+                )),
+            },
+            "data" | "data-ascii" | "data-binary" | "data-raw" | "json"
+                if name != "data-raw" && value.starts_with('@') =>
+            {
+                notes.push(format!(
+                    "the request body is read from the file {}, it is not imported",
+                    &value[1..]
+                ))
+            }
+            "data" | "data-ascii" | "data-binary" | "data-raw" => data.push(value),
+            "json" => {
+                is_json = true;
+                data.push(value);
+            }
+            "data-urlencode" => match urlencode_field(&value) {
+                Some(field) => data.push(field),
+                None => notes.push(format!(
+                    "--data-urlencode {} reads a file, it is not imported",
+                    value
+                )),
+            },
+            "form" | "form-string" => form.push(value),
+            "user" => headers.push((
+                "Authorization".into(),
+                format!("Basic {}", STANDARD.encode(value)),
+            )),
+            "oauth2-bearer" => headers.push(("Authorization".into(), format!("Bearer {}", value))),
+            "user-agent" => headers.push(("User-Agent".into(), value)),
+            "referer" => headers.push(("Referer".into(), value)),
+            "cookie" if value.contains('=') => headers.push(("Cookie".into(), value)),
+            "cookie" => notes.push(format!(
+                "cookies are read from the file {}, they are not imported",
+                value
+            )),
+            "insecure" => request.ignore_ssl_errors = Some(true),
+            "location" => request.follow_redirects = Some(true),
+            "max-redirs" => match value.parse::<u64>() {
+                Ok(max) => request.max_redirects = Some(max),
+                Err(_) => notes.push(format!("--max-redirs {} is not a number", value)),
+            },
+            "http2-prior-knowledge" => request.http_version = Some("http-2".to_string()),
+            "max-time" => match value.parse::<f64>() {
+                Ok(seconds) => request.timeout = Some(seconds.ceil() as u64),
+                Err(_) => notes.push(format!("--max-time {} is not a number", value)),
+            },
+            name if IGNORED_OPTIONS.contains(&name) => {}
+            name => notes.push(format!(
+                "the curl option {} is not supported",
+                option_text(name)
+            )),
+        }
+    }
+
+    let Some(url) = url else {
+        return Err(format!("the curl command has no url: {}", args.join(" ")).into());
+    };
+    let url = if url.contains("://") {
+        url
+    } else {
+        format!("http://{}", url)
+    };
+    // The query string is written as params, without the encoding the url needs
+    let (url, query) = match url.split_once('?') {
+        Some((url, query)) => (url.to_string(), query.to_string()),
+        None => (url, String::new()),
+    };
+    let url = url.split('#').next().unwrap_or_default().to_string();
+    let mut query: Vec<String> = [query]
+        .into_iter()
+        .filter(|q| !q.is_empty())
+        .map(|q| q.split('#').next().unwrap_or_default().to_string())
+        .collect();
+
+    if is_json {
+        for (name, value) in [
+            ("Content-Type", "application/json"),
+            ("Accept", "application/json"),
+        ] {
+            if header(&headers, name).is_none() {
+                headers.push((name.into(), value.into()));
+            }
+        }
+    }
+    let has_body = !get && (!data.is_empty() || !form.is_empty());
+    if get {
+        query.append(&mut data);
+    } else if !form.is_empty() {
+        if !data.is_empty() {
+            notes.push("curl can't send -d and -F together, the -d data is not imported".into());
+        }
+        headers.retain(|(name, _)| !name.eq_ignore_ascii_case("content-type"));
+        headers.push((
+            "Content-Type".into(),
+            format!("multipart/form-data; boundary={}", BOUNDARY),
+        ));
+        request.raw = Some(multipart(&form, &mut notes));
+    } else if !data.is_empty() {
+        let body = data.join("&");
+        let content_type = header(&headers, "content-type").unwrap_or_default();
+        if content_type.contains("json") {
+            headers.retain(|(name, _)| !name.eq_ignore_ascii_case("content-type"));
+            request.json = Some(json_body(&body));
+        } else {
+            if content_type.is_empty() {
+                headers.push((
+                    "Content-Type".into(),
+                    "application/x-www-form-urlencoded".into(),
+                ));
+            }
+            request.raw = Some(body);
+        }
+    }
+
+    let mut params: HashMap<String, String> = HashMap::new();
+    let query = query.join("&");
+    if !query.is_empty() {
+        let parsed = Url::parse(&format!("http://localhost/?{}", query))?;
+        for (name, value) in parsed.query_pairs() {
+            if params.insert(name.to_string(), value.to_string()).is_some() {
+                notes.push(format!(
+                    "the query param {} is repeated, only its last value is imported",
+                    name
+                ));
+            }
+        }
+    }
+
+    let method = method.unwrap_or_else(|| if has_body { "POST" } else { "GET" }.to_string());
+    let path = Url::parse(&url)
+        .map(|u| u.path().to_string())
+        .unwrap_or_else(|_| url.clone());
+    let title = format!("{} {}", method, path);
+    request.http_method = HttpMethod::new(&method, url.clone()).unwrap_or_else(|| {
+        notes.push(format!(
+            "the {} method is not supported, sent as GET",
+            method
+        ));
+        HttpMethod::GET(url)
+    });
+
+    // Repeated headers are sent as one, with their values joined
+    let mut joined: Vec<(String, String)> = Vec::new();
+    for (name, value) in headers {
+        match joined
+            .iter_mut()
+            .find(|(n, _)| n.eq_ignore_ascii_case(&name))
+        {
+            Some((_, joined)) => {
+                let separator = if name.eq_ignore_ascii_case("cookie") {
+                    "; "
+                } else {
+                    ", "
+                };
+                joined.push_str(separator);
+                joined.push_str(&value);
+            }
+            None => joined.push((name, value)),
+        }
+    }
+    request.headers = (!joined.is_empty()).then(|| joined.into_iter().collect());
+    request.params = (!params.is_empty()).then_some(params);
+
+    Ok(Step {
+        item: TestItem {
+            title: Some(title),
+            request,
+            ..Default::default()
+        },
+        notes,
+    })
+}
+
+fn header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .rev()
+        .find(|(n, _)| n.eq_ignore_ascii_case(name))
+        .map(|(_, v)| v.as_str())
+}
+
+fn option_text(name: &str) -> String {
+    match name.strip_prefix('-') {
+        Some(short) => format!("-{}", short),
+        None => format!("--{}", name),
+    }
+}
+
+// urlencode_field encodes the value of a --data-urlencode field, which is either `content`,
+// `=content` or `name=content`. Fields read from files, `name@file`, return None.
+fn urlencode_field(field: &str) -> Option<String> {
+    let encode = |s: &str| {
+        Url::parse_with_params("http://localhost/", [("", s)])
+            .ok()
+            .and_then(|u| u.query().map(|q| q[1..].to_string()))
+            .unwrap_or_default()
+    };
+    match field.find(['=', '@']) {
+        Some(i) if field[i..].starts_with('@') => None,
+        Some(0) => Some(encode(&field[1..])),
+        Some(i) => Some(format!("{}={}", &field[..i], encode(&field[i + 1..]))),
+        None => Some(encode(field)),
+    }
+}
+
+// multipart renders -F fields as a multipart/form-data body. File uploads can't be expressed in
+// a test file, so they become notes.
+fn multipart(fields: &[String], notes: &mut Vec<String>) -> String {
+    let mut body = String::new();
+    for field in fields {
+        let Some((name, value)) = field.split_once('=') else {
+            notes.push(format!("-F {} has no value, it is not imported", field));
+            continue;
+        };
+        if value.starts_with('@') || value.starts_with('<') {
+            notes.push(format!("-F {} uploads a file, it is not imported", field));
+            continue;
+        }
+        body.push_str(&format!(
+            "--{}\r\nContent-Disposition: form-data; name=\"{}\"\r\n\r\n{}\r\n",
+            BOUNDARY, name, value
+        ));
+    }
+    body.push_str(&format!("--{}--\r\n", BOUNDARY));
+    body
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_import() {
+        let plan = import(
+            r#"curl 'https://api.example.com/todos?page=2&q=a%20b' \
+  -X POST -H 'Content-Type: application/json' \
+  -H "Authorization: Bearer {{token}}" \
+  --data-raw $'{"task":"it\'s done"}' --compressed -sSk ;
+curl -u jane:secret -F name=jane -F avatar=@me.png localhost:3000/users &&
+curl -XPUT https://api.example.com/todos/1 -d done=true -d 'note=a b' --max-time 2.5 -L --max-redirs 3"#,
+        )
+        .unwrap();
+        assert_eq!(plan.steps.len(), 3);
+
+        let post = &plan.steps[0];
+        assert!(post.notes.is_empty(), "{:?}", post.notes);
+        let request = &post.item.request;
+        assert_eq!(post.item.title.as_deref(), Some("POST /todos"));
+        assert_eq!(
+            request.http_method,
+            HttpMethod::POST("https://api.example.com/todos".into())
+        );
+        assert_eq!(
+            request.params,
+            Some(HashMap::from([
+                ("page".to_string(), "2".to_string()),
+                ("q".to_string(), "a b".to_string())
+            ]))
+        );
+        assert_eq!(
+            request.headers,
+            Some(HashMap::from([(
+                "Authorization".to_string(),
+                "Bearer {{token}}".to_string()
+            )]))
+        );
+        assert_eq!(request.json, Some(json!({"task": "it's done"})));
+        assert_eq!(request.ignore_ssl_errors, Some(true));
+
+        let form = &plan.steps[1];
+        assert_eq!(
+            form.notes,
+            vec!["-F avatar=@me.png uploads a file, it is not imported".to_string()]
+        );
+        let request = &form.item.request;
+        assert_eq!(
+            request.http_method,
+            HttpMethod::POST("http://localhost:3000/users".into())
+        );
+        let headers = request.headers.as_ref().unwrap();
+        assert_eq!(headers["Authorization"], "Basic amFuZTpzZWNyZXQ=");
+        assert_eq!(
+            headers["Content-Type"],
+            "multipart/form-data; boundary=testkit-form-boundary"
+        );
+        assert!(
+            request
+                .raw
+                .as_ref()
+                .unwrap()
+                .contains("name=\"name\"\r\n\r\njane\r\n")
+        );
+
+        let put = &plan.steps[2].item.request;
+        assert_eq!(
+            put.http_method,
+            HttpMethod::PUT("https://api.example.com/todos/1".into())
+        );
+        assert_eq!(put.raw.as_deref(), Some("done=true&note=a b"));
+        assert_eq!(
+            put.headers.as_ref().unwrap()["Content-Type"],
+            "application/x-www-form-urlencoded"
+        );
+        assert_eq!(put.timeout, Some(3));
+        assert_eq!(put.follow_redirects, Some(true));
+        assert_eq!(put.max_redirects, Some(3));
+    }
+}
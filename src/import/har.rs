@@ -0,0 +1,511 @@
+use super::{Plan, Step, json_body, json_path, to_text};
+use crate::base_request::{Assert, HttpMethod, RequestConfig, TestItem};
+use regex::{Captures, Regex};
+use reqwest::Url;
+use serde_json::Value;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    error::Error,
+    fs,
+    path::Path,
+};
+
+// Headers the browser or the HTTP client sets on its own, which would only add noise to a plan
+const SKIPPED_HEADERS: &[&str] = &[
+    "host",
+    "content-length",
+    "connection",
+    "accept-encoding",
+    "accept-language",
+    "user-agent",
+    "referer",
+    "origin",
+    "priority",
+    "cache-control",
+    "pragma",
+    "if-none-match",
+    "if-modified-since",
+    "upgrade-insecure-requests",
+];
+
+const ASSET_EXTENSIONS: &[&str] = &[
+    "js", "mjs", "css", "map", "png", "jpg", "jpeg", "gif", "svg", "ico", "webp", "avif", "woff",
+    "woff2", "ttf", "otf", "eot", "mp4", "webm", "mp3",
+];
+
+const ASSET_RESOURCE_TYPES: &[&str] = &["script", "stylesheet", "image", "font", "media"];
+
+#[derive(Debug, Default, Clone)]
+pub struct HarOptions {
+    // Only entries for these hosts, or their subdomains, are imported. All hosts when empty
+    pub hosts: Vec<String>,
+    // Drops scripts, stylesheets, images, fonts and media
+    pub skip_assets: bool,
+    // Adds a type assertion for every top level field of a JSON response
+    pub assert_fields: bool,
+}
+
+// import turns every entry of a HAR file into a step. Values that a response returns and a later
+// request sends, like tokens and ids, become exports of the first and `{{var}}`s in the second.
+pub fn import(har: &Path, options: &HarOptions) -> Result<Plan, Box<dyn Error>> {
+    let source = fs::read_to_string(har)?;
+    let doc: Value = serde_json::from_str(&source)?;
+    let name = har
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_else(|| "har".to_string());
+    from_har(&doc, &name, options)
+}
+
+pub fn from_har(doc: &Value, name: &str, options: &HarOptions) -> Result<Plan, Box<dyn Error>> {
+    let Some(entries) = doc["log"]["entries"].as_array() else {
+        return Err("not a HAR file, it has no log.entries".into());
+    };
+    let mut recorded: Vec<Recorded> = entries
+        .iter()
+        .filter_map(Recorded::new)
+        .filter(|r| r.matches(options))
+        .collect();
+    let exports = correlate(&mut recorded);
+    Ok(Plan {
+        name: name.to_string(),
+        steps: recorded
+            .into_iter()
+            .zip(exports)
+            .map(|(r, exports)| r.into_step(exports, options))
+            .collect(),
+        ..Default::default()
+    })
+}
+
+// Recorded is a HAR entry, in the parts a step is made of
+struct Recorded {
+    method: String,
+    url: Url,
+    path: String,
+    params: Vec<(String, String)>,
+    headers: Vec<(String, String)>,
+    body: Option<String>,
+    body_is_json: bool,
+    resource_type: String,
+    mime_type: String,
+    status: u64,
+    response: Option<Value>,
+}
+
+impl Recorded {
+    fn new(entry: &Value) -> Option<Self> {
+        let request = &entry["request"];
+        let url = Url::parse(request["url"].as_str()?).ok()?;
+        let mut path = url.clone();
+        path.set_query(None);
+        path.set_fragment(None);
+        let mut params: Vec<(String, String)> = request["queryString"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(|q| (to_text(&q["name"]), to_text(&q["value"])))
+            .collect();
+        // Some tools leave queryString empty, the url still has it
+        if params.is_empty() {
+            params = url.query_pairs().into_owned().collect();
+        }
+        let headers = request["headers"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(|h| (to_text(&h["name"]), to_text(&h["value"])))
+            .filter(|(name, _)| {
+                let name = name.to_lowercase();
+                !name.starts_with(':')
+                    && !name.starts_with("sec-")
+                    && !SKIPPED_HEADERS.contains(&name.as_str())
+            })
+            .collect();
+        let post_data = &request["postData"];
+        let body = post_data["text"]
+            .as_str()
+            .filter(|t| !t.is_empty())
+            .map(String::from);
+        let content = &entry["response"]["content"];
+        let mime_type = content["mimeType"].as_str().unwrap_or_default().to_string();
+        // Base64 encoded bodies are binary, so there's nothing to correlate in them
+        let response = content["text"]
+            .as_str()
+            .filter(|_| content["encoding"] != "base64" && mime_type.contains("json"))
+            .and_then(|text| serde_json::from_str(text).ok());
+        Some(Recorded {
+            method: request["method"].as_str().unwrap_or("GET").to_uppercase(),
+            path: path.to_string(),
+            url,
+            params,
+            headers,
+            body_is_json: post_data["mimeType"]
+                .as_str()
+                .unwrap_or_default()
+                .contains("json"),
+            body,
+            resource_type: entry["_resourceType"]
+                .as_str()
+                .unwrap_or_default()
+                .to_string(),
+            mime_type,
+            status: entry["response"]["status"].as_u64().unwrap_or_default(),
+            response,
+        })
+    }
+
+    fn matches(&self, options: &HarOptions) -> bool {
+        let host = self.url.host_str().unwrap_or_default();
+        if !options.hosts.is_empty()
+            && !options
+                .hosts
+                .iter()
+                .any(|h| host == h || host.ends_with(&format!(".{}", h)))
+        {
+            return false;
+        }
+        if options.skip_assets {
+            let extension = self
+                .url
+                .path()
+                .rsplit_once('.')
+                .map(|(_, e)| e.to_lowercase());
+            let is_asset = ASSET_RESOURCE_TYPES.contains(&self.resource_type.as_str())
+                || extension.is_some_and(|e| ASSET_EXTENSIONS.contains(&e.as_str()))
+                || [
+                    "image/",
+                    "font/",
+                    "text/css",
+                    "javascript",
+                    "video/",
+                    "audio/",
+                ]
+                .iter()
+                .any(|m| self.mime_type.contains(m));
+            if is_asset {
+                return false;
+            }
+        }
+        true
+    }
+
+    // request_text is everything a request sends, to tell values the client made up from
+    // values it got from a response
+    fn request_text(&self) -> String {
+        let mut text = self.url.to_string();
+        for (_, value) in self.params.iter().chain(&self.headers) {
+            text.push('\n');
+            text.push_str(value);
+        }
+        if let Some(body) = &self.body {
+            text.push('\n');
+            text.push_str(body);
+        }
+        text
+    }
+
+    // replace swaps every standalone occurrence of value for `{{name}}`, returning whether any was found
+    fn replace(&mut self, value: &Value, name: &str) -> bool {
+        let text = to_text(value);
+        let re = Regex::new(&format!(
+            r"(^|[^A-Za-z0-9_\-]){}($|[^A-Za-z0-9_\-])",
+            regex::escape(&text)
+        ))
+        .unwrap();
+        let var = format!("{{{{{}}}}}", name);
+        let mut found = false;
+        let mut swap = |s: &str| -> String {
+            if !re.is_match(s) {
+                return s.to_string();
+            }
+            found = true;
+            re.replace_all(s, |caps: &Captures| {
+                format!("{}{}{}", &caps[1], var, &caps[2])
+            })
+            .to_string()
+        };
+
+        self.path = swap(&self.path);
+        for (_, v) in self.params.iter_mut().chain(self.headers.iter_mut()) {
+            *v = swap(v);
+        }
+        if let Some(body) = &self.body {
+            // Exports are written into JSON bodies as JSON, quotes included
+            let target = if self.body_is_json {
+                value.to_string()
+            } else {
+                text
+            };
+            let re = Regex::new(&format!(
+                r"(^|[^A-Za-z0-9_\-]){}($|[^A-Za-z0-9_\-])",
+                regex::escape(&target)
+            ))
+            .unwrap();
+            if re.is_match(body) {
+                found = true;
+                self.body = Some(
+                    re.replace_all(body, |caps: &Captures| {
+                        format!("{}{}{}", &caps[1], var, &caps[2])
+                    })
+                    .to_string(),
+                );
+            }
+        }
+        found
+    }
+
+    fn into_step(self, exports: HashMap<String, String>, options: &HarOptions) -> Step {
+        let mut notes = Vec::new();
+        let mut json = None;
+        let mut raw = None;
+        if let Some(body) = self.body {
+            if self.body_is_json {
+                json = Some(json_body(&body));
+            } else {
+                raw = Some(body);
+            }
+        }
+        let mut asserts = Vec::new();
+        if self.status != 0 {
+            asserts.push(Assert::IsOk(format!("$.resp.status == {}", self.status)));
+        }
+        if let Some(Value::Object(fields)) =
+            self.response.as_ref().filter(|_| options.assert_fields)
+        {
+            for (key, value) in fields {
+                let path = json_path("$.resp.json", key);
+                asserts.push(match value {
+                    Value::String(_) => Assert::IsString(path),
+                    Value::Number(_) => Assert::IsNumber(path),
+                    Value::Bool(_) => Assert::IsBoolean(path),
+                    Value::Array(_) => Assert::IsArray(path),
+                    Value::Null => Assert::IsNull(path),
+                    Value::Object(_) => Assert::Exists(path),
+                });
+            }
+        }
+        let url = self.path;
+        let http_method = HttpMethod::new(&self.method, url.clone()).unwrap_or_else(|| {
+            notes.push(format!(
+                "the {} method is not supported, sent as GET",
+                self.method
+            ));
+            HttpMethod::GET(url)
+        });
+        let params: HashMap<String, String> = self.params.into_iter().collect();
+        let headers: HashMap<String, String> = self.headers.into_iter().collect();
+        let item = TestItem {
+            title: Some(format!("{} {}", self.method, self.url.path())),
+            request: RequestConfig {
+                http_method,
+                headers: (!headers.is_empty()).then_some(headers),
+                json,
+                params: (!params.is_empty()).then_some(params),
+                raw,
+                ..Default::default()
+            },
+            asserts: (!asserts.is_empty()).then_some(asserts),
+            exports: (!exports.is_empty()).then_some(exports),
+            ..Default::default()
+        };
+        Step { item, notes }
+    }
+}
+
+// correlate finds values from a response that a later request sends again, and replaces them
+// with exports. It returns the exports of every step.
+fn correlate(recorded: &mut [Recorded]) -> Vec<HashMap<String, String>> {
+    let mut exports: Vec<HashMap<String, String>> = vec![HashMap::new(); recorded.len()];
+    // The names already given to (step, jsonpath) pairs
+    let mut names: HashMap<(usize, String), String> = HashMap::new();
+    let mut taken: HashSet<String> = HashSet::new();
+    // Candidate values, mapped to the latest response they came from. Longer values go first, so
+    // a value that contains another is replaced as a whole.
+    let mut sources: BTreeMap<(std::cmp::Reverse<usize>, String), (Value, usize, String)> =
+        BTreeMap::new();
+    let mut sent = String::new();
+
+    for (j, entry) in recorded.iter_mut().enumerate() {
+        for (value, step, path) in sources.values() {
+            if !entry.request_text().contains(&to_text(value)) {
+                continue;
+            }
+            let key = (*step, path.clone());
+            let name = names
+                .get(&key)
+                .cloned()
+                .unwrap_or_else(|| export_name(path, *step, &taken));
+            if entry.replace(value, &name) && !names.contains_key(&key) {
+                taken.insert(name.clone());
+                names.insert(key, name.clone());
+                exports[*step].insert(name, path.clone());
+            }
+        }
+
+        sent.push_str(&entry.request_text());
+        let mut leaves = Vec::new();
+        if let Some(response) = &entry.response {
+            collect_leaves(response, "$.resp.json".to_string(), &mut leaves);
+        }
+        for (path, value) in leaves {
+            let text = to_text(&value);
+            // Values the client sent before it got this response weren't issued by the server
+            if sent.contains(&text) {
+                continue;
+            }
+            sources.insert((std::cmp::Reverse(text.len()), text), (value, j, path));
+        }
+    }
+    exports
+}
+
+// collect_leaves gathers the values that look like tokens or ids: strings of 5 or more characters
+// without spaces, and integers of 2 or more digits.
+fn collect_leaves(value: &Value, path: String, leaves: &mut Vec<(String, Value)>) {
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map {
+                collect_leaves(value, json_path(&path, key), leaves);
+            }
+        }
+        Value::Array(values) => {
+            for (i, value) in values.iter().enumerate() {
+                collect_leaves(value, format!("{}[{}]", path, i), leaves);
+            }
+        }
+        Value::String(s) if s.len() >= 5 && !s.contains(char::is_whitespace) => {
+            leaves.push((path, value.clone()))
+        }
+        Value::Number(n) if n.as_u64().is_some_and(|n| n >= 10) => {
+            leaves.push((path, value.clone()))
+        }
+        _ => {}
+    }
+}
+
+// export_name names an export after the last key of its jsonpath, eg `$.resp.json.user.id` is
+// exported as `id`, or as `id_3` when step 3's `id` isn't the first one
+fn export_name(path: &str, step: usize, taken: &HashSet<String>) -> String {
+    let name: String = path
+        .rsplit(['.', '['])
+        .map(|part| part.trim_matches(|c| c == ']' || c == '\''))
+        .find(|part| !part.is_empty() && !part.chars().all(|c| c.is_ascii_digit()))
+        .unwrap_or("value")
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    if taken.contains(&name) {
+        format!("{}_{}", name, step)
+    } else {
+        name
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn entry(
+        method: &str,
+        url: &str,
+        headers: Value,
+        body: Option<Value>,
+        response: Value,
+    ) -> Value {
+        let mut entry = json!({
+            "request": { "method": method, "url": url, "headers": headers, "queryString": [] },
+            "response": {
+                "status": 200,
+                "content": { "mimeType": "application/json", "text": response.to_string() }
+            }
+        });
+        if let Some(body) = body {
+            entry["request"]["postData"] =
+                json!({ "mimeType": "application/json", "text": body.to_string() });
+        }
+        entry
+    }
+
+    #[test]
+    fn test_from_har() {
+        let mut asset = entry(
+            "GET",
+            "https://app.example.com/main.js",
+            json!([]),
+            None,
+            json!({}),
+        );
+        asset["response"]["content"]["mimeType"] = json!("application/javascript");
+        let doc = json!({ "log": { "entries": [
+            entry(
+                "POST",
+                "https://api.example.com/login",
+                json!([{ "name": "Content-Type", "value": "application/json" }]),
+                Some(json!({ "user": "jane" })),
+                json!({ "token": "abc.def.ghi", "user": "jane" })
+            ),
+            asset,
+            entry("GET", "https://tracker.io/collect", json!([]), None, json!({})),
+            entry(
+                "POST",
+                "https://api.example.com/todos",
+                json!([
+                    { "name": "Authorization", "value": "Bearer abc.def.ghi" },
+                    { "name": ":authority", "value": "api.example.com" }
+                ]),
+                Some(json!({ "task": "run tests" })),
+                json!({ "todo": { "id": 42, "task": "run tests" } })
+            ),
+            entry(
+                "PUT",
+                "https://api.example.com/todos/42?notify=1",
+                json!([{ "name": "Authorization", "value": "Bearer abc.def.ghi" }]),
+                Some(json!({ "id": 42, "done": true })),
+                json!({})
+            ),
+        ]}});
+        let options = HarOptions {
+            hosts: vec!["example.com".into()],
+            skip_assets: true,
+            ..Default::default()
+        };
+        let plan = from_har(&doc, "journey", &options).unwrap();
+        let items: Vec<&TestItem> = plan.steps.iter().map(|s| &s.item).collect();
+        assert_eq!(items.len(), 3);
+
+        assert_eq!(
+            items[0].exports,
+            Some(HashMap::from([(
+                "token".to_string(),
+                "$.resp.json.token".to_string()
+            )]))
+        );
+        assert_eq!(
+            items[1].request.headers.as_ref().unwrap()["Authorization"],
+            "Bearer {{token}}"
+        );
+        assert_eq!(
+            items[1].exports,
+            Some(HashMap::from([(
+                "id".to_string(),
+                "$.resp.json.todo.id".to_string()
+            )]))
+        );
+        assert_eq!(
+            items[2].request.http_method,
+            HttpMethod::PUT("https://api.example.com/todos/{{id}}".into())
+        );
+        // The query string is sent as params, and values the client sent itself are kept
+        assert_eq!(items[2].request.params.as_ref().unwrap()["notify"], "1");
+        assert_eq!(
+            items[2].request.json,
+            Some(Value::String(r#"{"id":{{id}},"done":true}"#.into()))
+        );
+        assert_eq!(
+            items[2].asserts,
+            Some(vec![Assert::IsOk("$.resp.status == 200".into())])
+        );
+    }
+}
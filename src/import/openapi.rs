@@ -0,0 +1,400 @@
+use super::{Plan, Step, json_path};
+use crate::base_request::{Assert, HttpMethod, RequestConfig, TestItem};
+use serde_json::{Map, Value};
+use std::{collections::HashMap, error::Error, fs, path::Path};
+
+// The operations that are imported, every method OpenAPI 3.0 and 3.1 have a key for. Anything
+// else, like CONNECT, which a spec can't describe, or the `query` and `additionalOperations` of
+// OpenAPI 3.2, is skipped
+const METHODS: &[&str] = &[
+    "get", "post", "put", "patch", "delete", "head", "options", "trace",
+];
+
+// Guards against specs with recursive schemas
+const MAX_DEPTH: usize = 8;
+
+// import generates one step per operation of an OpenAPI 3 spec, written as YAML or JSON.
+pub fn import(spec: &Path) -> Result<Plan, Box<dyn Error>> {
+    let source = fs::read_to_string(spec)?;
+    let doc: Value = serde_yaml::from_str(&source)?;
+    if let Some(server) = doc["servers"][0]["url"].as_str() {
+        log::info!(target:"testkit", "Set BASE_URL={} in your .env to run the generated steps", server);
+    }
+    Ok(Plan {
+        name: doc["info"]["title"]
+            .as_str()
+            .unwrap_or("openapi")
+            .to_string(),
+        steps: from_spec(&doc)?.into_iter().map(Step::from).collect(),
+        ..Default::default()
+    })
+}
+
+pub fn from_spec(doc: &Value) -> Result<Vec<TestItem>, Box<dyn Error>> {
+    let version = doc["openapi"].as_str().unwrap_or_default();
+    if !version.starts_with('3') {
+        return Err(format!(
+            "only OpenAPI 3 specs are supported, this spec has openapi: {:?}",
+            version
+        )
+        .into());
+    }
+    let spec = Spec { doc };
+    let mut items = Vec::new();
+    for (path, path_item) in doc["paths"].as_object().into_iter().flatten() {
+        let path_item = spec.resolve(path_item);
+        for method in METHODS {
+            if let Some(operation) = path_item.get(*method) {
+                items.push(spec.operation(path, method, path_item, operation));
+            }
+        }
+    }
+    Ok(items)
+}
+
+struct Spec<'a> {
+    doc: &'a Value,
+}
+
+impl<'a> Spec<'a> {
+    // resolve follows local `$ref`s, like `#/components/schemas/Todo`
+    fn resolve<'b>(&self, value: &'b Value) -> &'b Value
+    where
+        'a: 'b,
+    {
+        let mut value = value;
+        for _ in 0..MAX_DEPTH {
+            let Some(reference) = value["$ref"].as_str() else {
+                break;
+            };
+            match reference
+                .strip_prefix('#')
+                .and_then(|pointer| self.doc.pointer(pointer))
+            {
+                Some(target) => value = target,
+                None => break,
+            }
+        }
+        value
+    }
+
+    fn operation(
+        &self,
+        path: &str,
+        method: &str,
+        path_item: &Value,
+        operation: &Value,
+    ) -> TestItem {
+        let title = operation["summary"]
+            .as_str()
+            .or(operation["operationId"].as_str())
+            .map(String::from)
+            .unwrap_or_else(|| format!("{} {}", method.to_uppercase(), path));
+
+        // Operation parameters override path level parameters with the same name and location
+        let mut parameters: Vec<&Value> = Vec::new();
+        for param in path_item["parameters"]
+            .as_array()
+            .into_iter()
+            .chain(operation["parameters"].as_array())
+            .flatten()
+        {
+            let param = self.resolve(param);
+            parameters.retain(|p| p["name"] != param["name"] || p["in"] != param["in"]);
+            parameters.push(param);
+        }
+
+        let mut url = format!("$.env.BASE_URL{}", path);
+        let mut params = HashMap::new();
+        let mut headers = HashMap::new();
+        for param in parameters {
+            let name = param["name"].as_str().unwrap_or_default();
+            let required = param["required"].as_bool().unwrap_or(false);
+            let example = self.param_example(param);
+            match param["in"].as_str() {
+                Some("path") => {
+                    url = url.replace(&format!("{{{}}}", name), &to_param(&example));
+                }
+                Some("query") if required || !param["example"].is_null() => {
+                    params.insert(name.to_string(), to_param(&example));
+                }
+                Some("header") if required => {
+                    headers.insert(name.to_string(), to_param(&example));
+                }
+                _ => {}
+            }
+        }
+
+        let json = json_media(&self.resolve(&operation["requestBody"])["content"])
+            .map(|media| self.media_example(media));
+
+        TestItem {
+            title: Some(title),
+            request: RequestConfig {
+                http_method: http_method(method, url),
+                headers: (!headers.is_empty()).then_some(headers),
+                json,
+                params: (!params.is_empty()).then_some(params),
+                ..Default::default()
+            },
+            asserts: Some(self.asserts(operation)),
+            tags: operation["tags"].as_array().map(|tags| {
+                tags.iter()
+                    .filter_map(|t| t.as_str().map(String::from))
+                    .collect()
+            }),
+            ..Default::default()
+        }
+    }
+
+    // asserts checks the first documented success code, and the types of the required
+    // properties of its response body.
+    fn asserts(&self, operation: &Value) -> Vec<Assert> {
+        let Some(responses) = operation["responses"].as_object() else {
+            return vec![];
+        };
+        let mut codes: Vec<&String> = responses
+            .keys()
+            .filter(|code| code.parse::<u16>().is_ok())
+            .collect();
+        codes.sort_by(|a, b| (!a.starts_with('2'), a).cmp(&(!b.starts_with('2'), b)));
+        let Some(code) = codes.first() else {
+            return vec![];
+        };
+        let mut asserts = vec![Assert::IsOk(format!("$.resp.status == {}", code))];
+
+        let response = self.resolve(&responses[code.as_str()]);
+        let Some(media) = json_media(&response["content"]) else {
+            return asserts;
+        };
+        let (required, properties) = self.object_parts(&media["schema"], 0);
+        for name in required {
+            let path = json_path("$.resp.json", &name);
+            let schema = properties.get(&name).map(|s| self.resolve(s));
+            asserts.push(match schema.and_then(|s| s["type"].as_str()) {
+                Some("string") => Assert::IsString(path),
+                Some("integer") | Some("number") => Assert::IsNumber(path),
+                Some("boolean") => Assert::IsBoolean(path),
+                Some("array") => Assert::IsArray(path),
+                _ => Assert::Exists(path),
+            });
+        }
+        asserts
+    }
+
+    // object_parts returns the required property names and the properties of an object schema,
+    // merging the parts of an allOf.
+    fn object_parts(&self, schema: &Value, depth: usize) -> (Vec<String>, Map<String, Value>) {
+        let schema = self.resolve(schema);
+        let mut required: Vec<String> = schema["required"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|r| r.as_str().map(String::from))
+            .collect();
+        let mut properties = schema["properties"]
+            .as_object()
+            .cloned()
+            .unwrap_or_default();
+        if depth < MAX_DEPTH {
+            for part in schema["allOf"].as_array().into_iter().flatten() {
+                let (part_required, part_properties) = self.object_parts(part, depth + 1);
+                required.extend(part_required);
+                properties.extend(part_properties);
+            }
+        }
+        (required, properties)
+    }
+
+    fn param_example(&self, param: &Value) -> Value {
+        if !param["example"].is_null() {
+            return param["example"].clone();
+        }
+        if let Some(example) = first_example(&param["examples"]) {
+            return self.resolve(example)["value"].clone();
+        }
+        self.sample(&param["schema"], 0)
+    }
+
+    fn media_example(&self, media: &Value) -> Value {
+        if !media["example"].is_null() {
+            return media["example"].clone();
+        }
+        if let Some(example) = first_example(&media["examples"]) {
+            return self.resolve(example)["value"].clone();
+        }
+        self.sample(&media["schema"], 0)
+    }
+
+    // sample builds an example value for a schema, preferring the examples and defaults it documents
+    fn sample(&self, schema: &Value, depth: usize) -> Value {
+        let schema = self.resolve(schema);
+        if depth > MAX_DEPTH {
+            return Value::Null;
+        }
+        for key in ["example", "default"] {
+            if !schema[key].is_null() {
+                return schema[key].clone();
+            }
+        }
+        if let Some(value) = schema["enum"].get(0) {
+            return value.clone();
+        }
+        for key in ["oneOf", "anyOf"] {
+            if let Some(first) = schema[key].get(0) {
+                return self.sample(first, depth + 1);
+            }
+        }
+        if schema["allOf"].is_array() || schema["properties"].is_object() {
+            let (_, properties) = self.object_parts(schema, depth);
+            return Value::Object(
+                properties
+                    .iter()
+                    .map(|(name, property)| (name.clone(), self.sample(property, depth + 1)))
+                    .collect(),
+            );
+        }
+        match schema["type"].as_str() {
+            Some("array") => Value::Array(vec![self.sample(&schema["items"], depth + 1)]),
+            Some("object") => Value::Object(Map::new()),
+            Some("integer") | Some("number") if schema["minimum"].is_number() => {
+                schema["minimum"].clone()
+            }
+            Some("integer") | Some("number") => Value::from(1),
+            Some("boolean") => Value::Bool(true),
+            Some("string") => Value::String(
+                match schema["format"].as_str() {
+                    Some("date-time") => "2024-01-01T00:00:00Z",
+                    Some("date") => "2024-01-01",
+                    Some("email") => "user@example.com",
+                    Some("uuid") => "00000000-0000-0000-0000-000000000000",
+                    Some("uri") | Some("url") => "https://example.com",
+                    _ => "string",
+                }
+                .to_string(),
+            ),
+            _ => Value::Null,
+        }
+    }
+}
+
+fn first_example(examples: &Value) -> Option<&Value> {
+    examples.as_object().and_then(|e| e.values().next())
+}
+
+// json_media picks the JSON media type of a request or response content map
+fn json_media(content: &Value) -> Option<&Value> {
+    let content = content.as_object()?;
+    content.get("application/json").or_else(|| {
+        content
+            .iter()
+            .find(|(k, _)| k.contains("json"))
+            .map(|(_, v)| v)
+    })
+}
+
+fn to_param(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Null => String::new(),
+        v => v.to_string(),
+    }
+}
+
+fn http_method(method: &str, url: String) -> HttpMethod {
+    match method {
+        "post" => HttpMethod::POST(url),
+        "put" => HttpMethod::PUT(url),
+        "patch" => HttpMethod::PATCH(url),
+        "delete" => HttpMethod::DELETE(url),
+        "head" => HttpMethod::HEAD(url),
+        "options" => HttpMethod::OPTIONS(url),
+        "trace" => HttpMethod::TRACE(url),
+        _ => HttpMethod::GET(url),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_spec() {
+        let doc: Value = serde_yaml::from_str(
+            r##"
+openapi: 3.0.0
+paths:
+  /todos/{id}:
+    parameters:
+      - name: id
+        in: path
+        required: true
+        schema: { type: integer, example: 42 }
+    get:
+      summary: get todo
+      tags: [todos]
+      parameters:
+        - name: expand
+          in: query
+          example: owner
+      responses:
+        "404": { description: not found }
+        "200":
+          description: ok
+          content:
+            application/json:
+              schema: { $ref: "#/components/schemas/Todo" }
+  /todos:
+    post:
+      operationId: createTodo
+      requestBody:
+        content:
+          application/json:
+            schema: { $ref: "#/components/schemas/Todo" }
+      responses:
+        "201": { description: created }
+components:
+  schemas:
+    Todo:
+      type: object
+      required: [id, task, done]
+      properties:
+        id: { type: integer }
+        task: { type: string, example: run tests }
+        done: { type: boolean }
+"##,
+        )
+        .unwrap();
+        let items = from_spec(&doc).unwrap();
+        assert_eq!(items.len(), 2);
+
+        let get = &items[0];
+        assert_eq!(get.title.as_deref(), Some("get todo"));
+        assert_eq!(
+            get.request.http_method,
+            HttpMethod::GET("$.env.BASE_URL/todos/42".into())
+        );
+        assert_eq!(get.request.params.as_ref().unwrap()["expand"], "owner");
+        assert_eq!(get.tags, Some(vec!["todos".to_string()]));
+        assert_eq!(
+            get.asserts,
+            Some(vec![
+                Assert::IsOk("$.resp.status == 200".into()),
+                Assert::IsNumber("$.resp.json.id".into()),
+                Assert::IsString("$.resp.json.task".into()),
+                Assert::IsBoolean("$.resp.json.done".into()),
+            ])
+        );
+
+        let post = &items[1];
+        assert_eq!(post.title.as_deref(), Some("createTodo"));
+        assert_eq!(
+            post.request.json,
+            Some(serde_json::json!({"id": 1, "task": "run tests", "done": true}))
+        );
+        assert_eq!(
+            post.asserts,
+            Some(vec![Assert::IsOk("$.resp.status == 201".into())])
+        );
+    }
+}
@@ -0,0 +1,456 @@
+use super::{Plan, Step, json_body, to_text};
+use crate::base_request::{Assert, HttpMethod, RequestConfig, TestItem};
+use regex::Regex;
+use serde_json::Value;
+use std::{collections::HashMap, error::Error, fs, path::Path};
+
+// import converts a Postman collection, v2.0 or v2.1, into test plans. Requests at the root of
+// the collection make up the first plan, and every folder becomes a plan of its own.
+pub fn import(collection: &Path) -> Result<Vec<Plan>, Box<dyn Error>> {
+    let source = fs::read_to_string(collection)?;
+    let doc: Value = serde_json::from_str(&source)?;
+    from_collection(&doc)
+}
+
+pub fn from_collection(doc: &Value) -> Result<Vec<Plan>, Box<dyn Error>> {
+    let schema = doc["info"]["schema"].as_str().unwrap_or_default();
+    if !schema.contains("collection") {
+        return Err("not a Postman collection. Export it as Collection v2.1 and try again".into());
+    }
+    let converter = Converter::new();
+
+    // Collection variables are referenced as {{name}}, just like testkit exports
+    let mut collection_notes = Vec::new();
+    for var in doc["variable"].as_array().into_iter().flatten() {
+        collection_notes.push(format!(
+            "collection variable {} = {}",
+            var["key"].as_str().unwrap_or_default(),
+            to_text(&var["value"])
+        ));
+    }
+    converter.scripts(&doc["event"], "collection", &mut collection_notes);
+    converter.auth(&doc["auth"], "collection", &mut collection_notes, &mut None);
+
+    let mut plans = vec![Plan {
+        name: doc["info"]["name"]
+            .as_str()
+            .unwrap_or("postman")
+            .to_string(),
+        ..Default::default()
+    }];
+    converter.walk(&doc["item"], 0, "", &mut plans);
+    let plans = plans
+        .into_iter()
+        .filter(|plan| !plan.steps.is_empty() || !plan.notes.is_empty())
+        .map(|mut plan| {
+            plan.notes.splice(0..0, collection_notes.iter().cloned());
+            plan
+        })
+        .collect();
+    Ok(plans)
+}
+
+struct Converter {
+    variable: Regex,
+    json_alias: Regex,
+    have_status: Regex,
+    expect_eql: Regex,
+    expect_exists: Regex,
+    set_variable: Regex,
+    test_block: Regex,
+}
+
+impl Converter {
+    fn new() -> Self {
+        let re = |pattern: &str| Regex::new(pattern).unwrap();
+        Converter {
+            variable: re(r"\{\{([^{}]+)\}\}"),
+            json_alias: re(r"^(?:var|let|const)\s+(\w+)\s*=\s*pm\.response\.json\(\)\s*;?$"),
+            have_status: re(r"^pm\.response\.to\.have\.status\((\d+)\)\s*;?$"),
+            expect_eql: re(r"^pm\.expect\((.+)\)\.to\.(?:be\.)?(?:eql|equal|eq)\((.+)\)\s*;?$"),
+            expect_exists: re(r"^pm\.expect\((.+)\)\.to\.(?:exist|not\.be\.undefined)\s*;?$"),
+            set_variable: re(
+                r#"^pm\.(?:environment|collectionVariables|globals|variables)\.set\(\s*["'](\w+)["']\s*,\s*(.+?)\s*\)\s*;?$"#,
+            ),
+            test_block: re(r"^(pm\.test\(.*\{|\}\s*\)\s*;?)$"),
+        }
+    }
+
+    fn walk(&self, items: &Value, plan: usize, prefix: &str, plans: &mut Vec<Plan>) {
+        for item in items.as_array().into_iter().flatten() {
+            let name = item["name"].as_str().unwrap_or_default();
+            if item["item"].is_array() {
+                let folder = format!("{}{}", prefix, name);
+                let mut notes = Vec::new();
+                self.scripts(&item["event"], "folder", &mut notes);
+                self.auth(&item["auth"], "folder", &mut notes, &mut None);
+                plans.push(Plan {
+                    name: folder.clone(),
+                    notes,
+                    ..Default::default()
+                });
+                let index = plans.len() - 1;
+                self.walk(&item["item"], index, &format!("{} / ", folder), plans);
+                continue;
+            }
+            match self.step(item) {
+                Ok(step) => plans[plan].steps.push(step),
+                Err(note) => plans[plan].notes.push(note),
+            }
+        }
+    }
+
+    // step converts a single request. Requests that can't be sent by testkit at all are
+    // returned as an Err note instead.
+    fn step(&self, item: &Value) -> Result<Step, String> {
+        let name = item["name"].as_str().unwrap_or_default();
+        let request = &item["request"];
+        let mut notes = Vec::new();
+
+        let mut params = HashMap::new();
+        let url = match &request["url"] {
+            Value::Object(url) => {
+                for query in url
+                    .get("query")
+                    .and_then(|q| q.as_array())
+                    .into_iter()
+                    .flatten()
+                {
+                    if !query["disabled"].as_bool().unwrap_or(false) {
+                        params.insert(to_text(&query["key"]), to_text(&query["value"]));
+                    }
+                }
+                let raw = url.get("raw").and_then(|r| r.as_str()).unwrap_or_default();
+                raw.split('?').next().unwrap_or_default().to_string()
+            }
+            _ => {
+                let raw = request["url"]
+                    .as_str()
+                    .or(request.as_str())
+                    .unwrap_or_default();
+                let (url, query) = raw.split_once('?').unwrap_or((raw, ""));
+                for pair in query.split('&').filter(|p| !p.is_empty()) {
+                    let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+                    params.insert(key.to_string(), value.to_string());
+                }
+                url.to_string()
+            }
+        };
+
+        let method = request["method"].as_str().unwrap_or("GET");
+        let Some(http_method) = HttpMethod::new(method, url) else {
+            return Err(format!(
+                "skipped `{}`: the {} method is not supported",
+                name, method
+            ));
+        };
+
+        let mut headers = HashMap::new();
+        for header in request["header"].as_array().into_iter().flatten() {
+            if !header["disabled"].as_bool().unwrap_or(false) {
+                headers.insert(to_text(&header["key"]), to_text(&header["value"]));
+            }
+        }
+        let mut authorization = None;
+        self.auth(&request["auth"], "request", &mut notes, &mut authorization);
+        if let Some(authorization) = authorization {
+            headers.insert("Authorization".to_string(), authorization);
+        }
+
+        let mut json = None;
+        let body = &request["body"];
+        match body["mode"].as_str() {
+            None => {}
+            Some("raw") => {
+                let raw = body["raw"].as_str().unwrap_or_default();
+                let is_json = body["options"]["raw"]["language"] == "json"
+                    || headers
+                        .iter()
+                        .any(|(k, v)| k.eq_ignore_ascii_case("content-type") && v.contains("json"));
+                match serde_json::from_str::<Value>(raw) {
+                    _ if raw.trim().is_empty() => {}
+                    _ if is_json => json = Some(json_body(raw)),
+                    Ok(value) => json = Some(value),
+                    Err(_) => notes.push(format!("raw body is not translated:\n{}", raw)),
+                }
+            }
+            Some(mode) => {
+                let mut note = format!("{} body is not translated", mode);
+                for field in body[mode].as_array().into_iter().flatten() {
+                    note.push_str(&format!(
+                        "\n{}={}",
+                        to_text(&field["key"]),
+                        to_text(&field["value"])
+                    ));
+                }
+                notes.push(note);
+            }
+        }
+
+        let mut item_out = TestItem {
+            title: Some(name.to_string()),
+            request: RequestConfig {
+                http_method,
+                headers: (!headers.is_empty()).then_some(headers),
+                json,
+                params: (!params.is_empty()).then_some(params),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        self.tests(&item["event"], &mut item_out, &mut notes);
+        self.scripts(&item["event"], "request", &mut notes);
+
+        // testkit variables may only contain letters, digits and underscores
+        let source = serde_json::to_string(&item_out).unwrap_or_default();
+        for var in self.variable.captures_iter(&source) {
+            let var_name = &var[1];
+            if !var_name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_')
+            {
+                notes.push(format!(
+                    "{{{{{}}}}} is not a valid testkit variable name",
+                    var_name
+                ));
+            }
+        }
+
+        Ok(Step {
+            item: item_out,
+            notes,
+        })
+    }
+
+    // auth turns bearer auth into an Authorization header and notes any other kind of auth
+    fn auth(
+        &self,
+        auth: &Value,
+        owner: &str,
+        notes: &mut Vec<String>,
+        header: &mut Option<String>,
+    ) {
+        match auth["type"].as_str() {
+            None | Some("noauth") => {}
+            Some("bearer") if owner == "request" => {
+                let token = auth["bearer"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .find(|field| field["key"] == "token")
+                    .map(|field| to_text(&field["value"]))
+                    .unwrap_or_default();
+                *header = Some(format!("Bearer {}", token));
+            }
+            Some(kind) => notes.push(format!("{} {} auth is not translated", owner, kind)),
+        }
+    }
+
+    // scripts notes pre-request scripts, which testkit has no equivalent for
+    fn scripts(&self, events: &Value, owner: &str, notes: &mut Vec<String>) {
+        for event in events.as_array().into_iter().flatten() {
+            let listen = event["listen"].as_str().unwrap_or_default();
+            if listen == "test" && owner == "request" {
+                continue;
+            }
+            let lines = script_lines(event);
+            if !lines.is_empty() {
+                notes.push(format!(
+                    "{} {} script is not translated:\n{}",
+                    owner,
+                    listen,
+                    lines.join("\n")
+                ));
+            }
+        }
+    }
+
+    // tests translates simple pm.expect and pm.response assertions, and variables set from
+    // the response, into asserts and exports.
+    fn tests(&self, events: &Value, item: &mut TestItem, notes: &mut Vec<String>) {
+        let mut aliases: Vec<String> = Vec::new();
+        let mut asserts = Vec::new();
+        let mut exports = HashMap::new();
+        let mut untranslated = Vec::new();
+        for event in events.as_array().into_iter().flatten() {
+            if event["listen"] != "test" {
+                continue;
+            }
+            for line in script_lines(event) {
+                let line = line.trim();
+                if line.is_empty() || line.starts_with("//") || self.test_block.is_match(line) {
+                    continue;
+                }
+                if let Some(caps) = self.json_alias.captures(line) {
+                    aliases.push(caps[1].to_string());
+                    continue;
+                }
+                if let Some(caps) = self.have_status.captures(line) {
+                    asserts.push(Assert::IsOk(format!("$.resp.status == {}", &caps[1])));
+                    continue;
+                }
+                if let Some(caps) = self.expect_eql.captures(line) {
+                    if let Some(path) = response_path(&caps[1], &aliases) {
+                        asserts.push(Assert::IsOk(format!("{} == {}", path, literal(&caps[2]))));
+                        continue;
+                    }
+                }
+                if let Some(caps) = self.expect_exists.captures(line) {
+                    if let Some(path) = response_path(&caps[1], &aliases) {
+                        asserts.push(Assert::Exists(path));
+                        continue;
+                    }
+                }
+                if let Some(caps) = self.set_variable.captures(line) {
+                    if let Some(path) = response_path(&caps[2], &aliases) {
+                        exports.insert(caps[1].to_string(), path);
+                        continue;
+                    }
+                }
+                untranslated.push(line.to_string());
+            }
+        }
+        if !asserts.is_empty() {
+            item.asserts = Some(asserts);
+        }
+        if !exports.is_empty() {
+            item.exports = Some(exports);
+        }
+        if !untranslated.is_empty() {
+            notes.push(format!(
+                "test script lines that were not translated:\n{}",
+                untranslated.join("\n")
+            ));
+        }
+    }
+}
+
+// response_path turns a reference to the response into a jsonpath, eg
+// `pm.response.json().user.id` or `jsonData.user.id` into `$.resp.json.user.id`
+fn response_path(expr: &str, aliases: &[String]) -> Option<String> {
+    let expr = expr.trim();
+    if expr == "pm.response.code" || expr == "responseCode.code" {
+        return Some("$.resp.status".to_string());
+    }
+    let rest = expr.strip_prefix("pm.response.json()").or_else(|| {
+        aliases.iter().find_map(|alias| {
+            expr.strip_prefix(alias.as_str())
+                .filter(|rest| rest.is_empty() || rest.starts_with(['.', '[']))
+        })
+    })?;
+    let valid = rest
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || "._[]'\"".contains(c));
+    valid.then(|| format!("$.resp.json{}", rest))
+}
+
+// literal turns a javascript literal into one the assertion engine understands
+fn literal(value: &str) -> String {
+    let value = value.trim();
+    match value.strip_prefix('\'').and_then(|v| v.strip_suffix('\'')) {
+        Some(inner) => format!("\"{}\"", inner),
+        None => value.to_string(),
+    }
+}
+
+fn script_lines(event: &Value) -> Vec<String> {
+    match &event["script"]["exec"] {
+        Value::Array(lines) => lines.iter().map(to_text).collect(),
+        Value::String(script) => script.lines().map(String::from).collect(),
+        _ => vec![],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::import::to_yaml;
+
+    #[test]
+    fn test_from_collection() {
+        let doc: Value = serde_json::from_str(
+            r#"{
+  "info": {"name": "Todos", "schema": "https://schema.getpostman.com/json/collection/v2.1.0/collection.json"},
+  "variable": [{"key": "baseUrl", "value": "http://localhost:3000"}],
+  "item": [
+    {"name": "health", "request": {"method": "GET", "url": "{{baseUrl}}/health"}},
+    {"name": "Todo items", "item": [
+      {
+        "name": "create todo",
+        "request": {
+          "method": "POST",
+          "auth": {"type": "bearer", "bearer": [{"key": "token", "value": "{{token}}"}]},
+          "header": [{"key": "Content-Type", "value": "application/json"}],
+          "body": {"mode": "raw", "raw": "{\"task\": \"run tests\", \"list\": {{listId}}}"},
+          "url": {"raw": "{{baseUrl}}/todos?notify=true", "query": [{"key": "notify", "value": "true"}]}
+        },
+        "event": [{"listen": "test", "script": {"exec": [
+          "var jsonData = pm.response.json();",
+          "pm.test(\"created\", function () {",
+          "    pm.expect(pm.response.code).to.eql(201);",
+          "    pm.expect(jsonData.task).to.eql('run tests');",
+          "});",
+          "pm.environment.set(\"todoId\", jsonData.id);",
+          "console.log(jsonData);"
+        ]}}]
+      },
+      {"name": "options", "request": {"method": "OPTIONS", "url": "{{baseUrl}}/todos"}}
+    ]}
+  ]
+}"#,
+        )
+        .unwrap();
+        let plans = from_collection(&doc).unwrap();
+        assert_eq!(plans.len(), 2);
+        assert_eq!(plans[0].name, "Todos");
+        assert_eq!(
+            plans[0].steps[0].item.request.http_method,
+            HttpMethod::GET("{{baseUrl}}/health".into())
+        );
+
+        let folder = &plans[1];
+        assert_eq!(folder.name, "Todo items");
+        assert_eq!(
+            folder.notes,
+            vec!["collection variable baseUrl = http://localhost:3000"]
+        );
+        assert_eq!(
+            folder.steps[1].item.request.http_method,
+            HttpMethod::OPTIONS("{{baseUrl}}/todos".into())
+        );
+        let step = &folder.steps[0];
+        let request = &step.item.request;
+        assert_eq!(
+            request.http_method,
+            HttpMethod::POST("{{baseUrl}}/todos".into())
+        );
+        assert_eq!(request.params.as_ref().unwrap()["notify"], "true");
+        assert_eq!(
+            request.headers.as_ref().unwrap()["Authorization"],
+            "Bearer {{token}}"
+        );
+        assert_eq!(
+            request.json,
+            Some(Value::String(
+                r#"{"task": "run tests", "list": {{listId}}}"#.into()
+            ))
+        );
+        assert_eq!(
+            step.item.asserts,
+            Some(vec![
+                Assert::IsOk("$.resp.status == 201".into()),
+                Assert::IsOk(r#"$.resp.json.task == "run tests""#.into()),
+            ])
+        );
+        assert_eq!(
+            step.item.exports.as_ref().unwrap()["todoId"],
+            "$.resp.json.id"
+        );
+
+        let yaml = to_yaml(folder).unwrap();
+        assert!(yaml.starts_with("# collection variable baseUrl = http://localhost:3000\n"));
+        assert!(yaml.contains(
+            "# test script lines that were not translated:\n# console.log(jsonData);\n- title: create todo\n"
+        ));
+    }
+}
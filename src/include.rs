@@ -0,0 +1,232 @@
+use crate::base_request::{TestItem, TestPlan};
+use serde::Deserialize;
+use serde_json::Value;
+use serde_yaml::Value as YamlValue;
+use std::{
+    collections::HashMap,
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+// IncludeStep is a step that runs the steps of another file in its place,
+// eg `- include: ./auth/login.tk.yaml` with `with: { user: admin }`.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct IncludeStep {
+    include: String,
+    with: Option<HashMap<String, Value>>,
+}
+
+// Include is set on the steps of an included file. Errors of the steps point at the included
+// file, and the `with` params are set as variables while the steps run.
+#[derive(Debug, Clone, Default)]
+pub struct Include {
+    pub file: String,
+    pub source: Arc<String>,
+    pub with: HashMap<String, Value>,
+}
+
+// parse reads a test file, a list of steps or a plan with hooks, with the steps of the files it
+// includes in place of the `include` steps.
+pub fn parse(source: &str, file: &str) -> Result<TestPlan, Box<dyn Error>> {
+    let mut stack = vec![(canonical(Path::new(file)), file.to_string())];
+    let doc: YamlValue = serde_yaml::from_str(source)?;
+    if !doc.is_mapping() {
+        return Ok(TestPlan::from_steps(parse_file(source, file, &mut stack)?));
+    }
+    let plan: TestPlan<YamlValue> = serde_yaml::from_str(source)?;
+    // Parsing the whole file keeps the line numbers in the errors of files without includes
+    if !plan
+        .sections()
+        .iter()
+        .any(|(_, s)| s.iter().any(is_include))
+    {
+        return Ok(serde_yaml::from_str(source)?);
+    }
+    Ok(TestPlan {
+        before_all: parse_entries(plan.before_all, file, &mut stack)?,
+        before_each: parse_entries(plan.before_each, file, &mut stack)?,
+        steps: parse_entries(plan.steps, file, &mut stack)?,
+        after_each: parse_entries(plan.after_each, file, &mut stack)?,
+        after_all: parse_entries(plan.after_all, file, &mut stack)?,
+    })
+}
+
+fn parse_file(
+    source: &str,
+    file: &str,
+    stack: &mut Vec<(PathBuf, String)>,
+) -> Result<Vec<TestItem>, Box<dyn Error>> {
+    let entries: Vec<YamlValue> = serde_yaml::from_str(source)?;
+    // Parsing the whole file keeps the line numbers in the errors of files without includes
+    if !entries.iter().any(is_include) {
+        return Ok(serde_yaml::from_str(source)?);
+    }
+    parse_entries(entries, file, stack)
+}
+
+fn parse_entries(
+    entries: Vec<YamlValue>,
+    file: &str,
+    stack: &mut Vec<(PathBuf, String)>,
+) -> Result<Vec<TestItem>, Box<dyn Error>> {
+    let mut items = Vec::new();
+    for (i, entry) in entries.into_iter().enumerate() {
+        if is_include(&entry) {
+            items.extend(included_steps(&entry, file, stack)?);
+        } else {
+            let item =
+                serde_yaml::from_value(entry).map_err(|err| format!("step {}: {}", i, err))?;
+            items.push(item);
+        }
+    }
+    Ok(items)
+}
+
+pub fn is_include(entry: &YamlValue) -> bool {
+    entry.get("include").is_some()
+}
+
+// include_steps returns the steps an `include` step of the given file stands for
+pub fn include_steps(entry: &YamlValue, file: &str) -> Result<Vec<TestItem>, Box<dyn Error>> {
+    let mut stack = vec![(canonical(Path::new(file)), file.to_string())];
+    included_steps(entry, file, &mut stack)
+}
+
+fn included_steps(
+    entry: &YamlValue,
+    file: &str,
+    stack: &mut Vec<(PathBuf, String)>,
+) -> Result<Vec<TestItem>, Box<dyn Error>> {
+    let step: IncludeStep =
+        serde_yaml::from_value(entry.clone()).map_err(|err| format!("invalid include: {}", err))?;
+    let path = Path::new(file)
+        .parent()
+        .unwrap_or(Path::new(""))
+        .join(&step.include);
+    let included_file = path.to_string_lossy().to_string();
+    let source = fs::read_to_string(&path)
+        .map_err(|err| format!("error reading {}: {}", included_file, err))?;
+    // Hooks belong to the plan that is run, so an included file is a list of steps
+    if serde_yaml::from_str::<YamlValue>(&source).is_ok_and(|doc| doc.is_mapping()) {
+        return Err(format!(
+            "{} has hooks, only a list of steps can be included",
+            included_file
+        )
+        .into());
+    }
+
+    let canonical = canonical(&path);
+    // The stack holds the files being included, each with its path as written
+    if let Some(start) = stack.iter().position(|(p, _)| *p == canonical) {
+        let mut cycle: Vec<&str> = stack[start..].iter().map(|(_, f)| f.as_str()).collect();
+        cycle.push(&included_file);
+        return Err(format!("circular include: {}", cycle.join(" → ")).into());
+    }
+    stack.push((canonical, included_file.clone()));
+    let items = parse_file(&source, &included_file, stack)
+        .map_err(|err| format!("in {}: {}", included_file, err));
+    stack.pop();
+
+    let source = Arc::new(source);
+    let with = step.with.unwrap_or_default();
+    let mut items = items?;
+    for item in &mut items {
+        match &mut item.include {
+            // Steps of nested includes see the params of every include on the way, the closest first
+            Some(include) => {
+                for (name, value) in &with {
+                    include
+                        .with
+                        .entry(name.clone())
+                        .or_insert_with(|| value.clone());
+                }
+            }
+            None => {
+                item.include = Some(Include {
+                    file: included_file.clone(),
+                    source: source.clone(),
+                    with: with.clone(),
+                })
+            }
+        }
+    }
+    Ok(items)
+}
+
+// includes returns the files a test file includes directly, from its steps and hooks
+pub fn includes(source: &str, file: &str) -> Vec<PathBuf> {
+    let entries: Vec<YamlValue> = match serde_yaml::from_str::<YamlValue>(source) {
+        Ok(doc) if doc.is_mapping() => serde_yaml::from_value::<TestPlan<YamlValue>>(doc)
+            .map(|plan| {
+                plan.sections()
+                    .into_iter()
+                    .flat_map(|(_, s)| s.clone())
+                    .collect()
+            })
+            .unwrap_or_default(),
+        Ok(doc) => serde_yaml::from_value(doc).unwrap_or_default(),
+        Err(_) => Vec::new(),
+    };
+    entries
+        .iter()
+        .filter_map(|entry| entry.get("include")?.as_str())
+        .map(|include| {
+            canonical(
+                &Path::new(file)
+                    .parent()
+                    .unwrap_or(Path::new(""))
+                    .join(include),
+            )
+        })
+        .collect()
+}
+
+// canonical identifies a file for cycle detection, falling back to the path as given for
+// files that don't exist
+pub fn canonical(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn test_parse() {
+        let dir = env::temp_dir().join("testkit-test-include");
+        fs::create_dir_all(dir.join("auth")).unwrap();
+        fs::write(
+            dir.join("auth/login.tk.yaml"),
+            "- title: login\n  POST: /login\n  json: '{\"user\": {{user}}}'\n  exports:\n    token: $.resp.json.token\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("orders.tk.yaml"),
+            "- include: ./auth/login.tk.yaml\n  with: { user: admin }\n- title: list orders\n  GET: /orders\n",
+        )
+        .unwrap();
+        fs::write(dir.join("a.tk.yaml"), "- include: b.tk.yaml\n").unwrap();
+        fs::write(dir.join("b.tk.yaml"), "- include: ./a.tk.yaml\n").unwrap();
+
+        let file = dir.join("orders.tk.yaml").to_string_lossy().to_string();
+        let items = parse(&fs::read_to_string(&file).unwrap(), &file)
+            .unwrap()
+            .steps;
+        let file = dir.join("a.tk.yaml").to_string_lossy().to_string();
+        let err = parse(&fs::read_to_string(&file).unwrap(), &file).unwrap_err();
+        fs::remove_dir_all(&dir).ok();
+
+        let titles: Vec<_> = items.iter().map(|i| i.title.clone().unwrap()).collect();
+        assert_eq!(titles, vec!["login", "list orders"]);
+        let include = items[0].include.as_ref().unwrap();
+        assert!(include.file.ends_with("auth/login.tk.yaml"));
+        assert_eq!(include.with["user"], Value::String("admin".into()));
+        assert!(items[1].include.is_none());
+        assert!(err.to_string().contains("circular include"));
+        assert!(err.to_string().contains("a.tk.yaml → "));
+    }
+}
@@ -4,6 +4,16 @@ use std::ffi::CStr;
 
 pub mod base_cli;
 pub mod base_request;
+pub mod data;
+pub mod environment;
+pub mod export;
+pub mod import;
+pub mod include;
+pub mod load;
+pub mod record;
+pub mod report;
+pub mod schema;
+pub mod validate;
 
 #[no_mangle]
 pub extern "C" fn haskell_binding(
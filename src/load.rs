@@ -0,0 +1,573 @@
+use crate::{
+    base_request::{self, Hook, StepStatus, TestContext, TestPlan},
+    include, report,
+};
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    fmt, fs,
+    path::Path,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+// How often progress is logged while a load test runs
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(5);
+
+pub struct LoadOptions {
+    pub vus: usize,
+    pub duration: Duration,
+    pub ramp_up: Duration,
+    pub thresholds: Vec<Threshold>,
+}
+
+// parse_duration reads durations like `500ms`, `30s`, `2m`, `1h` or `1m30s`. Plain numbers are seconds.
+pub fn parse_duration(text: &str) -> Result<Duration, String> {
+    let text = text.trim();
+    if let Ok(seconds) = text.parse::<f64>() {
+        return Ok(Duration::from_secs_f64(seconds));
+    }
+    let mut total = Duration::ZERO;
+    let mut rest = text;
+    while !rest.is_empty() {
+        let number_len = rest
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(rest.len());
+        let number: f64 = rest[..number_len]
+            .parse()
+            .map_err(|_| format!("invalid duration {:?}, use eg 500ms, 30s or 2m", text))?;
+        rest = &rest[number_len..];
+        let unit_len = rest
+            .find(|c: char| c.is_ascii_digit() || c == '.')
+            .unwrap_or(rest.len());
+        let seconds = match &rest[..unit_len] {
+            "ms" => number / 1000.0,
+            "s" => number,
+            "m" => number * 60.0,
+            "h" => number * 3600.0,
+            unit => {
+                return Err(format!(
+                    "invalid duration unit {:?} in {:?}, use ms, s, m or h",
+                    unit, text
+                ));
+            }
+        };
+        total += Duration::from_secs_f64(seconds);
+        rest = &rest[unit_len..];
+    }
+    Ok(total)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Metric {
+    // A latency percentile, eg 95 for p95
+    Percentile(f64),
+    Avg,
+    Max,
+    // The share of requests that failed their assertions or couldn't be sent
+    ErrorRate,
+    // Requests per second
+    Rps,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Comparison {
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+// Threshold is a condition on the metrics of a load test, eg `p95 < 300ms` or `error_rate < 1%`.
+// Latencies are in milliseconds and the error rate is a fraction.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Threshold {
+    pub text: String,
+    pub metric: Metric,
+    pub comparison: Comparison,
+    pub value: f64,
+}
+
+impl FromStr for Threshold {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "invalid threshold {:?}, use eg `p95<300ms`, `avg<200ms`, `error_rate<1%` or `rps>100`",
+                text
+            )
+        };
+        let at = text.find(['<', '>']).ok_or_else(invalid)?;
+        let (metric, rest) = (text[..at].trim(), &text[at..]);
+        let (comparison, value) = if let Some(value) = rest.strip_prefix("<=") {
+            (Comparison::Le, value)
+        } else if let Some(value) = rest.strip_prefix(">=") {
+            (Comparison::Ge, value)
+        } else if let Some(value) = rest.strip_prefix('<') {
+            (Comparison::Lt, value)
+        } else {
+            (Comparison::Gt, &rest[1..])
+        };
+        let value = value.trim();
+
+        let metric = match metric {
+            "avg" => Metric::Avg,
+            "max" => Metric::Max,
+            "error_rate" => Metric::ErrorRate,
+            "rps" => Metric::Rps,
+            p => Metric::Percentile(
+                p.strip_prefix('p')
+                    .and_then(|n| n.parse::<f64>().ok())
+                    .filter(|n| (0.0..=100.0).contains(n))
+                    .ok_or_else(invalid)?,
+            ),
+        };
+        let value = match metric {
+            Metric::ErrorRate => match value.strip_suffix('%') {
+                Some(percent) => percent.trim().parse::<f64>().map(|p| p / 100.0),
+                None => value.parse::<f64>(),
+            }
+            .map_err(|_| invalid())?,
+            Metric::Rps => value.parse::<f64>().map_err(|_| invalid())?,
+            _ => parse_duration(value)?.as_secs_f64() * 1000.0,
+        };
+        Ok(Threshold {
+            text: text.to_string(),
+            metric,
+            comparison,
+            value,
+        })
+    }
+}
+
+impl Threshold {
+    fn passes(&self, actual: f64) -> bool {
+        match self.comparison {
+            Comparison::Lt => actual < self.value,
+            Comparison::Le => actual <= self.value,
+            Comparison::Gt => actual > self.value,
+            Comparison::Ge => actual >= self.value,
+        }
+    }
+}
+
+// StepStats are the requests, latencies, in milliseconds, and errors of a step across every
+// iteration. Requests that couldn't be sent count as errors but have no latency, so they
+// don't drag the latency metrics down.
+#[derive(Debug, Default, Clone)]
+pub struct StepStats {
+    pub title: String,
+    pub requests: usize,
+    pub latencies: Vec<f64>,
+    pub errors: usize,
+}
+
+impl StepStats {
+    fn add(&mut self, other: &StepStats) {
+        self.requests += other.requests;
+        self.latencies.extend(&other.latencies);
+        self.errors += other.errors;
+    }
+
+    fn metric(&self, metric: Metric, elapsed: Duration) -> f64 {
+        let requests = self.requests as f64;
+        match metric {
+            Metric::Percentile(p) => percentile(&self.latencies, p),
+            Metric::Avg if !self.latencies.is_empty() => {
+                self.latencies.iter().sum::<f64>() / self.latencies.len() as f64
+            }
+            Metric::Avg => 0.0,
+            Metric::Max => self.latencies.iter().cloned().fold(0.0, f64::max),
+            Metric::ErrorRate if requests > 0.0 => self.errors as f64 / requests,
+            Metric::ErrorRate => 0.0,
+            Metric::Rps => requests / elapsed.as_secs_f64().max(f64::EPSILON),
+        }
+    }
+}
+
+// percentile uses the nearest rank method, so it's always one of the measured latencies
+fn percentile(latencies: &[f64], p: f64) -> f64 {
+    if latencies.is_empty() {
+        return 0.0;
+    }
+    let mut sorted = latencies.to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    let rank = ((p / 100.0) * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+#[derive(Debug)]
+pub struct LoadResult {
+    pub steps: Vec<StepStats>,
+    pub iterations: usize,
+    pub elapsed: Duration,
+}
+
+impl LoadResult {
+    pub fn total(&self) -> StepStats {
+        let mut total = StepStats {
+            title: "total".to_string(),
+            ..Default::default()
+        };
+        for step in &self.steps {
+            total.add(step);
+        }
+        total
+    }
+
+    // failed_thresholds returns the thresholds that the totals of the run broke
+    pub fn failed_thresholds<'a>(&self, thresholds: &'a [Threshold]) -> Vec<&'a Threshold> {
+        let total = self.total();
+        thresholds
+            .iter()
+            .filter(|t| !t.passes(total.metric(t.metric, self.elapsed)))
+            .collect()
+    }
+}
+
+// load_file runs a test plan as a load test, with the --var variables, and prints its results.
+// It returns the exit code.
+pub async fn load_file(file: &Path, options: &LoadOptions, vars: HashMap<String, Value>) -> i32 {
+    let plan = match fs::read_to_string(file)
+        .map_err(|err| err.to_string())
+        .and_then(|source| {
+            include::parse(&source, &file.to_string_lossy()).map_err(|err| err.to_string())
+        }) {
+        Ok(plan) => plan,
+        Err(err) => {
+            log::error!(target:"testkit", "💥 {}: invalid test file: {}", file.display(), err);
+            return report::EXIT_INVALID_FILE;
+        }
+    };
+    let ctx = TestContext {
+        plan: Some(file.to_string_lossy().to_string()),
+        file: file.to_string_lossy().to_string(),
+        vars,
+        ..Default::default()
+    };
+    log::info!(target:"testkit",
+        "Running {} with {} virtual users for {:?}, ramping up over {:?}",
+        file.display(),
+        options.vus,
+        options.duration,
+        options.ramp_up
+    );
+    let result = run(ctx, plan, options).await;
+    print_result(&result, &options.thresholds);
+    if result.failed_thresholds(&options.thresholds).is_empty() {
+        report::EXIT_OK
+    } else {
+        report::EXIT_THRESHOLD_FAILURE
+    }
+}
+
+// run starts the virtual users one after another over the ramp up, and has each of them run the
+// plan in a loop, with exports of its own, until the duration is over. A virtual user runs the
+// before_all and after_all hooks once, around its loop. Hooks aren't part of the stats.
+pub async fn run(ctx: TestContext, plan: TestPlan, options: &LoadOptions) -> LoadResult {
+    let start = Instant::now();
+    let deadline = start + options.duration;
+    let steps: Vec<StepStats> = plan
+        .steps
+        .iter()
+        .enumerate()
+        .map(|(i, item)| StepStats {
+            title: item.title.clone().unwrap_or(format!("step {}", i)),
+            ..Default::default()
+        })
+        .collect();
+    let stats = Arc::new(Mutex::new(steps));
+    let iterations = Arc::new(Mutex::new(0));
+    let plan = Arc::new(plan);
+
+    let mut handles = Vec::new();
+    for vu in 0..options.vus {
+        let delay = options.ramp_up.mul_f64(vu as f64 / options.vus as f64);
+        let (ctx, plan) = (ctx.clone(), plan.clone());
+        let (stats, iterations) = (stats.clone(), iterations.clone());
+        handles.push(tokio::spawn(async move {
+            tokio::time::sleep_until((start + delay).into()).await;
+            let mut exports_map = ctx.vars.clone();
+            let _ = base_request::run_hooks(
+                &ctx,
+                Hook::BeforeAll,
+                &plan.before_all,
+                None,
+                &mut exports_map,
+            )
+            .await;
+            // Every iteration starts from the exports of the before_all hooks, and the after_all
+            // hooks see the exports of the last iteration
+            let vu_exports = exports_map.clone();
+            'run: while Instant::now() < deadline {
+                exports_map = vu_exports.clone();
+                for (i, test_item) in plan.steps.iter().enumerate() {
+                    if Instant::now() >= deadline {
+                        break 'run;
+                    }
+                    let _ = base_request::run_hooks(
+                        &ctx,
+                        Hook::BeforeEach,
+                        &plan.before_each,
+                        None,
+                        &mut exports_map,
+                    )
+                    .await;
+                    {
+                        let results = base_request::run_item(
+                            &ctx,
+                            test_item,
+                            i as u32,
+                            None,
+                            &mut exports_map,
+                        )
+                        .await;
+                        let mut stats = stats.lock().unwrap();
+                        match results {
+                            Ok(results) => {
+                                for result in results {
+                                    let status = result.status();
+                                    if status == StepStatus::Skipped {
+                                        continue;
+                                    }
+                                    stats[i].requests += 1;
+                                    // A request that couldn't be sent has no latency
+                                    if status != StepStatus::Errored {
+                                        stats[i].latencies.push(result.timings.total_ms);
+                                    }
+                                    if status != StepStatus::Passed {
+                                        stats[i].errors += 1;
+                                    }
+                                }
+                            }
+                            Err(_) => {
+                                stats[i].requests += 1;
+                                stats[i].errors += 1;
+                            }
+                        }
+                    }
+                    let _ = base_request::run_hooks(
+                        &ctx,
+                        Hook::AfterEach,
+                        &plan.after_each,
+                        None,
+                        &mut exports_map,
+                    )
+                    .await;
+                }
+                *iterations.lock().unwrap() += 1;
+            }
+            let _ = base_request::run_hooks(
+                &ctx,
+                Hook::AfterAll,
+                &plan.after_all,
+                None,
+                &mut exports_map,
+            )
+            .await;
+        }));
+    }
+
+    let mut progress =
+        tokio::time::interval_at((start + PROGRESS_INTERVAL).into(), PROGRESS_INTERVAL);
+    let all_done = join_all(handles);
+    tokio::pin!(all_done);
+    loop {
+        tokio::select! {
+            _ = &mut all_done => break,
+            _ = progress.tick() => {
+                let elapsed = start.elapsed();
+                let active = (0..options.vus)
+                    .filter(|vu| options.ramp_up.mul_f64(*vu as f64 / options.vus as f64) <= elapsed)
+                    .count();
+                let (requests, errors) = stats
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .fold((0, 0), |(r, e), s| (r + s.requests, e + s.errors));
+                log::info!(target:"testkit",
+                    "⏱  {:.0?}/{:.0?}  VUs: {}/{}  requests: {}  errors: {}",
+                    elapsed,
+                    options.duration,
+                    active,
+                    options.vus,
+                    requests,
+                    errors
+                );
+            }
+        }
+    }
+
+    let steps = stats.lock().unwrap().clone();
+    let iterations = *iterations.lock().unwrap();
+    LoadResult {
+        steps,
+        iterations,
+        elapsed: start.elapsed(),
+    }
+}
+
+// join_all waits for every virtual user to finish
+async fn join_all(handles: Vec<tokio::task::JoinHandle<()>>) {
+    for handle in handles {
+        let _ = handle.await;
+    }
+}
+
+impl fmt::Display for LoadResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let title_width = self
+            .steps
+            .iter()
+            .map(|s| s.title.chars().count())
+            .max()
+            .unwrap_or(0)
+            .clamp(5, 40);
+        writeln!(
+            f,
+            "{:<title_width$}  {:>8}  {:>8}  {:>7}  {:>8}  {:>8}  {:>8}  {:>8}  {:>8}",
+            "step", "requests", "rps", "errors", "avg", "p50", "p95", "p99", "max"
+        )?;
+        let total = self.total();
+        for stats in self.steps.iter().chain([&total]) {
+            let title: String = stats.title.chars().take(title_width).collect();
+            let ms = |metric| format!("{:.1}ms", stats.metric(metric, self.elapsed));
+            writeln!(
+                f,
+                "{:<title_width$}  {:>8}  {:>8.1}  {:>6.2}%  {:>8}  {:>8}  {:>8}  {:>8}  {:>8}",
+                title,
+                stats.requests,
+                stats.metric(Metric::Rps, self.elapsed),
+                stats.metric(Metric::ErrorRate, self.elapsed) * 100.0,
+                ms(Metric::Avg),
+                ms(Metric::Percentile(50.0)),
+                ms(Metric::Percentile(95.0)),
+                ms(Metric::Percentile(99.0)),
+                ms(Metric::Max),
+            )?;
+        }
+        write!(f, "{} iterations in {:.2?}", self.iterations, self.elapsed)
+    }
+}
+
+fn print_result(result: &LoadResult, thresholds: &[Threshold]) {
+    eprintln!();
+    eprintln!("{}", result);
+    if thresholds.is_empty() {
+        return;
+    }
+    let failed = result.failed_thresholds(thresholds);
+    let total = result.total();
+    eprintln!();
+    for threshold in thresholds {
+        let actual = total.metric(threshold.metric, result.elapsed);
+        let actual = match threshold.metric {
+            Metric::ErrorRate => format!("{:.2}%", actual * 100.0),
+            Metric::Rps => format!("{:.1}", actual),
+            _ => format!("{:.1}ms", actual),
+        };
+        let mark = if failed.contains(&threshold) {
+            "❌"
+        } else {
+            "✅"
+        };
+        eprintln!("{} {} (actual: {})", mark, threshold.text, actual);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::base_request::TestItem;
+    use httpmock::prelude::*;
+
+    #[test]
+    fn test_thresholds() {
+        assert_eq!(parse_duration("1m30s"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("250ms"), Ok(Duration::from_millis(250)));
+        assert!(parse_duration("2 weeks").is_err());
+
+        let p95: Threshold = "p95 < 300ms".parse().unwrap();
+        assert_eq!(p95.metric, Metric::Percentile(95.0));
+        assert_eq!(p95.value, 300.0);
+        let errors: Threshold = "error_rate<=1%".parse().unwrap();
+        assert_eq!((errors.comparison, errors.value), (Comparison::Le, 0.01));
+        assert!("p101<1s".parse::<Threshold>().is_err());
+
+        let result = LoadResult {
+            steps: vec![StepStats {
+                title: "get todos".into(),
+                requests: 100,
+                latencies: (1..=100).map(f64::from).collect(),
+                errors: 1,
+            }],
+            iterations: 100,
+            elapsed: Duration::from_secs(10),
+        };
+        assert_eq!(percentile(&result.steps[0].latencies, 95.0), 95.0);
+        let thresholds: Vec<Threshold> = ["p95<100ms", "error_rate<1%", "rps>=10", "max<2s"]
+            .iter()
+            .map(|t| t.parse().unwrap())
+            .collect();
+        let failed: Vec<&str> = result
+            .failed_thresholds(&thresholds)
+            .iter()
+            .map(|t| t.text.as_str())
+            .collect();
+        assert_eq!(failed, vec!["error_rate<1%"]);
+    }
+
+    #[tokio::test]
+    async fn test_run() {
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.method(GET).path("/todos");
+            then.status(200).json_body(serde_json::json!([]));
+        });
+        let test_items: Vec<TestItem> = serde_yaml::from_str(&format!(
+            r#"
+- title: list todos
+  GET: {}
+  asserts:
+    - ok: $.resp.status == 201
+"#,
+            server.url("/todos")
+        ))
+        .unwrap();
+        let options = LoadOptions {
+            vus: 3,
+            duration: Duration::from_millis(300),
+            ramp_up: Duration::from_millis(100),
+            thresholds: vec![],
+        };
+        let result = run(
+            TestContext::default(),
+            TestPlan::from_steps(test_items),
+            &options,
+        )
+        .await;
+        let step = &result.steps[0];
+        assert!(step.latencies.len() >= 3);
+        assert_eq!(step.latencies.len(), mock.hits());
+        assert_eq!(step.requests, step.latencies.len());
+        assert_eq!(step.errors, step.latencies.len());
+        assert_eq!(result.iterations, step.latencies.len());
+
+        // Requests that can't be sent are errors without a latency
+        let test_items: Vec<TestItem> =
+            serde_yaml::from_str("- GET: http://127.0.0.1:1/todos").unwrap();
+        let result = run(
+            TestContext::default(),
+            TestPlan::from_steps(test_items),
+            &options,
+        )
+        .await;
+        let step = &result.steps[0];
+        assert!(step.requests >= 3);
+        assert_eq!(step.errors, step.requests);
+        assert!(step.latencies.is_empty());
+        assert_eq!(step.metric(Metric::Avg, result.elapsed), 0.0);
+    }
+}
@@ -1,16 +1,32 @@
+mod app;
 pub mod base_cli;
 pub mod base_request;
-use anyhow::Ok;
-use base_cli::Commands;
-use base_request::TestContext;
+pub mod data;
+pub mod environment;
+pub mod export;
+pub mod import;
+pub mod include;
+pub mod load;
+pub mod record;
+pub mod report;
+pub mod schema;
+pub mod validate;
+mod watch;
+use base_cli::{Commands, ExportTarget, ImportSource, Reporter};
+use base_request::{StepFilter, TestContext};
 use clap::Parser;
-use dotenv::dotenv;
 use log::LevelFilter;
+use report::{FileCallback, FileResult, Summary, json::NdjsonWriter};
 use std::{
     fs,
+    io::Read,
     path::{Path, PathBuf},
+    process,
     str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant},
 };
+use tokio::sync::Semaphore;
 
 use walkdir::WalkDir;
 
@@ -18,7 +34,6 @@ extern crate log;
 
 #[tokio::main]
 async fn main() {
-    dotenv().ok();
     let cli_instance = base_cli::Cli::parse();
 
     let mut builder = env_logger::Builder::from_default_env();
@@ -28,60 +43,456 @@ async fn main() {
         .filter_level(LevelFilter::from_str(&cli_instance.log_level).unwrap_or(LevelFilter::Info))
         .filter_module("jsonpath_lib", LevelFilter::Info)
         .init();
+    if let Err(err) = environment::load(cli_instance.env.as_deref()) {
+        log::error!(target:"testkit", "{}", err);
+        process::exit(1);
+    }
 
     match cli_instance.command {
-        None | Some(Commands::App {}) => {}
-        Some(Commands::Test { file }) => cli(file).await.unwrap(),
+        None => {}
+        Some(Commands::App {}) => {
+            if let Err(err) = app::run(Path::new(".")).await {
+                log::error!(target:"testkit", "Error running the app: {}", err);
+                process::exit(1);
+            }
+        }
+        Some(Commands::Import { source }) => {
+            let result = match source {
+                ImportSource::Openapi { spec, output } => import::openapi::import(&spec)
+                    .and_then(|plan| import::write_plans(&[plan], output.as_deref())),
+                ImportSource::Postman { collection, output } => {
+                    import::postman::import(&collection)
+                        .and_then(|plans| import::write_plans(&plans, output.as_deref()))
+                }
+                ImportSource::Curl { command, output } => read_arg(&command)
+                    .and_then(|commands| import::curl::import(&commands))
+                    .and_then(|plan| import::write_plans(&[plan], output.as_deref())),
+                ImportSource::Har {
+                    har,
+                    host,
+                    skip_assets,
+                    output,
+                } => {
+                    let options = import::har::HarOptions {
+                        hosts: host,
+                        skip_assets,
+                        ..Default::default()
+                    };
+                    import::har::import(&har, &options)
+                        .and_then(|plan| import::write_plans(&[plan], output.as_deref()))
+                }
+            };
+            if let Err(err) = result {
+                log::error!(target:"testkit", "Error importing: {}", err);
+                process::exit(1);
+            }
+        }
+        Some(Commands::Record {
+            listen,
+            target,
+            output,
+        }) => {
+            let options = record::RecordOptions {
+                listen,
+                target,
+                output,
+            };
+            if let Err(err) = record::run(options).await {
+                log::error!(target:"testkit", "Error recording: {}", err);
+                process::exit(1);
+            }
+        }
+        Some(Commands::Export { target }) => {
+            let ExportTarget::Curl { file, output } = target;
+            let result = export::export_curl(&file).and_then(|curl| match &output {
+                Some(path) => Ok(fs::write(path, curl)?),
+                None => {
+                    print!("{}", curl);
+                    Ok(())
+                }
+            });
+            if let Err(err) = result {
+                log::error!(target:"testkit", "Error exporting {}: {}", file.display(), err);
+                process::exit(1);
+            }
+        }
+        Some(Commands::Load {
+            file,
+            vus,
+            duration,
+            ramp_up,
+            threshold,
+            vars,
+            vars_file,
+        }) => {
+            let vars = match environment::vars(&vars, vars_file.as_deref()) {
+                Ok(vars) => vars,
+                Err(err) => {
+                    log::error!(target:"testkit", "{}", err);
+                    process::exit(1);
+                }
+            };
+            let options = load::LoadOptions {
+                vus,
+                duration,
+                ramp_up,
+                thresholds: threshold,
+            };
+            process::exit(load::load_file(&file, &options, vars).await);
+        }
+        Some(Commands::Schema {}) => {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&schema::tk_schema()).unwrap_or_default()
+            );
+        }
+        Some(Commands::Validate {
+            file,
+            vars,
+            vars_file,
+        }) => {
+            let vars = match environment::vars(&vars, vars_file.as_deref()) {
+                Ok(vars) => vars.into_keys().collect(),
+                Err(err) => {
+                    log::error!(target:"testkit", "{}", err);
+                    process::exit(1);
+                }
+            };
+            let problems = validate::validate_files(&test_files(file), &vars);
+            if problems > 0 {
+                process::exit(report::EXIT_INVALID_FILE);
+            }
+        }
+        Some(Commands::Test {
+            file,
+            reporter,
+            output,
+            tag,
+            exclude_tag,
+            grep,
+            jobs,
+            watch,
+            vars,
+            vars_file,
+        }) => {
+            let start = Instant::now();
+            let vars = match environment::vars(&vars, vars_file.as_deref()) {
+                Ok(vars) => vars,
+                Err(err) => {
+                    log::error!(target:"testkit", "{}", err);
+                    process::exit(1);
+                }
+            };
+            let mut ctx = TestContext {
+                // With concurrent jobs, each file's log is printed as a whole once it finishes
+                should_log: jobs <= 1,
+                filter: StepFilter {
+                    tags: tag,
+                    exclude_tags: exclude_tag,
+                    grep,
+                },
+                vars,
+                ..Default::default()
+            };
+            let mut on_file: Option<FileCallback> = None;
+            let ndjson = match reporter {
+                Some(Reporter::Ndjson) => match NdjsonWriter::new(output.as_deref()) {
+                    Ok(writer) => Some(Arc::new(writer)),
+                    Err(err) => {
+                        log::error!(target:"testkit", "Error opening report output: {}", err);
+                        None
+                    }
+                },
+                _ => None,
+            };
+            if let Some(ndjson) = &ndjson {
+                let step_writer = ndjson.clone();
+                ctx.on_step = Some(Arc::new(move |file, result| step_writer.step(file, result)));
+                let file_writer = ndjson.clone();
+                on_file = Some(Arc::new(move |file_result| file_writer.file(file_result)));
+            }
+
+            let run = TestRun {
+                ctx,
+                on_file,
+                jobs,
+                reporter,
+                output,
+                ndjson,
+                environment: cli_instance.env,
+            };
+            if watch {
+                if let Err(err) = watch::watch(file, &run).await {
+                    log::error!(target:"testkit", "Error watching files: {}", err);
+                    process::exit(1);
+                }
+                return;
+            }
+            let file_results = cli(test_files(file), &run).await;
+            let summary = finish_run(&file_results, start, &run);
+            process::exit(summary.exit_code());
+        }
     }
 }
 
-async fn cli(file_op: Option<PathBuf>) -> Result<(), anyhow::Error> {
+// TestRun holds everything `testkit test` needs to run a set of files and report on them.
+struct TestRun {
+    ctx: TestContext,
+    on_file: Option<FileCallback>,
+    jobs: usize,
+    reporter: Option<Reporter>,
+    output: Option<PathBuf>,
+    ndjson: Option<Arc<NdjsonWriter>>,
+    environment: Option<String>,
+}
+
+fn test_files(file_op: Option<PathBuf>) -> Vec<PathBuf> {
     match file_op {
-        Some(file) => {
-            let content = fs::read_to_string(file.clone())?;
+        Some(file) => vec![file],
+        None => find_tk_yaml_files(Path::new(".")),
+    }
+}
+
+// finish_run prints the summary of a run and writes its report, if a reporter was chosen.
+fn finish_run(file_results: &[FileResult], start: Instant, run: &TestRun) -> Summary {
+    let mut summary = Summary::from_results(file_results, start.elapsed());
+    summary.environment = run.environment.clone();
+    report::print_summary(file_results, &summary);
+    if let Some(ndjson) = &run.ndjson {
+        ndjson.summary(&summary);
+    }
+    if let Some(reporter) = run.reporter {
+        if let Err(err) =
+            report::write_report(reporter, run.output.as_deref(), file_results, &summary)
+        {
+            log::error!(target:"testkit", "Error writing report: {}", err);
+        }
+    }
+    summary
+}
+
+async fn cli(files: Vec<PathBuf>, run: &TestRun) -> Vec<FileResult> {
+    let (ctx, on_file, jobs) = (&run.ctx, &run.on_file, run.jobs);
+    let mut file_results = Vec::new();
+    if jobs <= 1 {
+        for file in files {
+            let file_result = run_file(file, ctx.clone()).await;
+            if let Some(on_file) = on_file {
+                on_file(&file_result);
+            }
+            file_results.push(file_result);
+        }
+        return file_results;
+    }
+
+    let semaphore = Arc::new(Semaphore::new(jobs));
+    let mut handles = Vec::new();
+    for file in files {
+        let semaphore = semaphore.clone();
+        let ctx = ctx.clone();
+        let on_file = on_file.clone();
+        let file_name = file.to_string_lossy().to_string();
+        let handle = tokio::spawn(async move {
+            let _permit = semaphore.acquire_owned().await;
+            let file_result = run_file(file, ctx).await;
+            report::print_file_log(&file_result);
+            if let Some(on_file) = &on_file {
+                on_file(&file_result);
+            }
+            file_result
+        });
+        handles.push((file_name, handle));
+    }
+    // Awaiting the handles in order keeps the results in the same order as the files
+    for (file_name, handle) in handles {
+        let file_result = handle.await.unwrap_or_else(|err| FileResult {
+            file: file_name,
+            results: Err(format!("test run panicked: {}", err)),
+            duration: Duration::ZERO,
+        });
+        file_results.push(file_result);
+    }
+    file_results
+}
+
+async fn run_file(file: PathBuf, ctx: TestContext) -> FileResult {
+    let start = Instant::now();
+    let file_name = file.to_string_lossy().to_string();
+    let results = match fs::read_to_string(&file) {
+        Ok(content) => {
             let ctx = TestContext {
-                file: file.to_str().unwrap().into(),
+                file: file_name.clone(),
                 file_source: content.clone(),
-                should_log: true,
-                ..Default::default()
+                ..ctx
             };
-            let _ = base_request::run(ctx, content).await;
-            Ok(())
-        }
-        None => {
-            let files = find_tk_yaml_files(Path::new("."));
-            for file in files {
-                let content = fs::read_to_string(file.clone())?;
-                let ctx = TestContext {
-                    file: file.to_str().unwrap().into(),
-                    file_source: content.clone(),
-                    should_log: true,
-                    ..Default::default()
-                };
-                let _ = base_request::run(ctx, content).await;
-            }
-            Ok(())
+            base_request::run(ctx, content)
+                .await
+                .map_err(|err| err.to_string())
         }
+        Err(err) => Err(err.to_string()),
+    };
+    FileResult {
+        file: file_name,
+        results,
+        duration: start.elapsed(),
+    }
+}
+
+// read_arg returns an argument as is, or stdin when the argument is `-`
+fn read_arg(arg: &str) -> Result<String, Box<dyn std::error::Error>> {
+    if arg != "-" {
+        return Ok(arg.to_string());
     }
+    let mut input = String::new();
+    std::io::stdin().read_to_string(&mut input)?;
+    Ok(input)
 }
 
 fn find_tk_yaml_files(dir: &Path) -> Vec<PathBuf> {
     let mut result = Vec::new();
-    for entry in WalkDir::new(dir).into_iter().filter_map(|e| e.ok()) {
-        if entry.file_type().is_file() {
-            if let Some(extension) = entry.path().extension() {
-                if extension == "yaml"
-                    && entry
-                        .path()
-                        .file_stem()
-                        .and_then(|n| n.to_str())
-                        .unwrap_or("")
-                        .contains(".tk")
-                {
-                    result.push(entry.path().to_path_buf());
-                }
-            }
+    for entry in WalkDir::new(dir)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_map(|e| e.ok())
+    {
+        if entry.file_type().is_file() && is_tk_yaml_file(entry.path()) {
+            result.push(entry.path().to_path_buf());
         }
     }
     result
 }
+
+fn is_tk_yaml_file(path: &Path) -> bool {
+    if let Some(extension) = path.extension() {
+        return extension == "yaml"
+            && path
+                .file_stem()
+                .and_then(|n| n.to_str())
+                .unwrap_or("")
+                .contains(".tk");
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use httpmock::prelude::*;
+    use serde_json::json;
+    use std::{ops::Deref, sync::Mutex, time::SystemTime};
+
+    // TestDir is a temp dir of its own for a test, removed when it is dropped
+    pub(crate) struct TestDir(PathBuf);
+
+    impl TestDir {
+        pub(crate) fn new(name: &str) -> Self {
+            let nanos = SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_nanos();
+            let dir = std::env::temp_dir().join(format!(
+                "testkit-test-{}-{}-{}",
+                name,
+                std::process::id(),
+                nanos
+            ));
+            fs::create_dir_all(&dir).unwrap();
+            TestDir(dir)
+        }
+    }
+
+    impl Deref for TestDir {
+        type Target = Path;
+
+        fn deref(&self) -> &Path {
+            &self.0
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            fs::remove_dir_all(&self.0).ok();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cli_jobs() {
+        let server = MockServer::start();
+        for name in ["a", "b"] {
+            server.mock(|when, then| {
+                when.method(GET).path(format!("/{}", name));
+                then.status(200)
+                    .delay(Duration::from_millis(300))
+                    .json_body(json!({ "file": name }));
+            });
+        }
+        let dir = TestDir::new("jobs");
+        let files: Vec<PathBuf> = ["a", "b"]
+            .iter()
+            .map(|name| {
+                let file = dir.join(format!("{}.tk.yaml", name));
+                let steps = format!(
+                    r#"
+- title: {name} first
+  GET: {url}
+  asserts:
+    - ok: $.resp.json.file == "{name}"
+- title: {name} second
+  GET: {url}
+  asserts:
+    - ok: $.resp.json.file == "{name}"
+"#,
+                    name = name,
+                    url = server.url(format!("/{}", name))
+                );
+                fs::write(&file, steps).unwrap();
+                file
+            })
+            .collect();
+
+        let finished = Arc::new(Mutex::new(Vec::new()));
+        let on_file: FileCallback = {
+            let finished = finished.clone();
+            Arc::new(move |file_result: &FileResult| {
+                finished.lock().unwrap().push(file_result.file.clone())
+            })
+        };
+        let run = TestRun {
+            ctx: TestContext::default(),
+            on_file: Some(on_file),
+            jobs: 2,
+            reporter: None,
+            output: None,
+            ndjson: None,
+            environment: None,
+        };
+        let start = Instant::now();
+        let file_results = cli(files.clone(), &run).await;
+        // Four requests of 300ms, sent two at a time
+        assert!(start.elapsed() < Duration::from_millis(1100));
+        assert_eq!(finished.lock().unwrap().len(), 2);
+
+        for (name, file_result) in ["a", "b"].iter().zip(&file_results) {
+            assert_eq!(
+                file_result.file,
+                files[0]
+                    .with_file_name(format!("{}.tk.yaml", name))
+                    .to_string_lossy()
+            );
+            let other = if *name == "a" { "b" } else { "a" };
+            let results = file_result.results.as_ref().unwrap();
+            let titles: Vec<&str> = results
+                .iter()
+                .map(|r| r.step_name.as_deref().unwrap())
+                .collect();
+            assert_eq!(
+                titles,
+                vec![format!("{} first", name), format!("{} second", name)]
+            );
+            for result in results {
+                assert!(result.step_log.contains(&format!("== \"{}\"", name)));
+                assert!(!result.step_log.contains(&format!("== \"{}\"", other)));
+            }
+        }
+    }
+}
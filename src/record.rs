@@ -0,0 +1,285 @@
+use crate::import::{self, har::HarOptions};
+use http_body_util::{BodyExt, Full};
+use hyper::{
+    HeaderMap, Request, Response, StatusCode,
+    body::{Bytes, Incoming},
+    header,
+    server::conn::http1,
+    service::service_fn,
+};
+use hyper_util::rt::TokioIo;
+use reqwest::Url;
+use serde_json::{Value, json};
+use std::{
+    convert::Infallible,
+    error::Error,
+    fs,
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+use tokio::net::TcpListener;
+
+// Headers that only concern a single connection, which a proxy must not forward
+const HOP_BY_HOP_HEADERS: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "proxy-authorization",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+    "host",
+    "content-length",
+];
+
+pub struct RecordOptions {
+    pub listen: SocketAddr,
+    pub target: Url,
+    pub output: PathBuf,
+}
+
+// Recorder forwards requests to the target and keeps every exchange as a HAR entry, so the
+// recording is converted the same way `testkit import har` converts browser sessions.
+struct Recorder {
+    client: reqwest::Client,
+    target: Url,
+    output: PathBuf,
+    entries: Mutex<Vec<Value>>,
+}
+
+// run serves a reverse proxy on listen until it's stopped with Ctrl-C. The test plan is rewritten
+// after every exchange, so nothing recorded is lost if the process is killed.
+pub async fn run(options: RecordOptions) -> Result<(), Box<dyn Error>> {
+    let listener = TcpListener::bind(options.listen).await?;
+    let recorder = Arc::new(Recorder {
+        // Redirects are passed on to the client, which follows them through the proxy
+        client: reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()?,
+        target: options.target,
+        output: options.output,
+        entries: Mutex::new(Vec::new()),
+    });
+    log::info!(target:"testkit",
+        "Recording requests to {} on http://{}, press Ctrl-C to stop",
+        recorder.target,
+        options.listen
+    );
+
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => {
+                let (stream, _) = accepted?;
+                let recorder = recorder.clone();
+                tokio::spawn(async move {
+                    let service = service_fn(move |request| {
+                        let recorder = recorder.clone();
+                        async move { Ok::<_, Infallible>(recorder.forward(request).await) }
+                    });
+                    if let Err(err) = http1::Builder::new()
+                        .serve_connection(TokioIo::new(stream), service)
+                        .await
+                    {
+                        log::debug!(target:"testkit", "Error serving connection: {}", err);
+                    }
+                });
+            }
+            _ = tokio::signal::ctrl_c() => break,
+        }
+    }
+
+    let recorded = recorder.entries.lock().unwrap().len();
+    log::info!(target:"testkit", "Recorded {} requests to {}", recorded, recorder.output.display());
+    Ok(())
+}
+
+impl Recorder {
+    async fn forward(&self, request: Request<Incoming>) -> Response<Full<Bytes>> {
+        let (parts, body) = request.into_parts();
+        let body = match body.collect().await {
+            Ok(body) => body.to_bytes(),
+            Err(err) => return bad_gateway(format!("Error reading the request body: {}", err)),
+        };
+        let path = parts.uri.path_and_query().map_or("/", |p| p.as_str());
+        let url = format!("{}{}", self.target.as_str().trim_end_matches('/'), path);
+
+        let mut headers = forwarded_headers(&parts.headers);
+        // Compressed responses couldn't be read into the recording
+        headers.remove(header::ACCEPT_ENCODING);
+        let response = self
+            .client
+            .request(parts.method.clone(), &url)
+            .headers(headers.clone())
+            .body(body.clone())
+            .send()
+            .await;
+        let response = match response {
+            Ok(response) => response,
+            Err(err) => return bad_gateway(format!("Error forwarding to {}: {}", url, err)),
+        };
+        let status = response.status();
+        let response_headers = forwarded_headers(response.headers());
+        let response_body = match response.bytes().await {
+            Ok(body) => body,
+            Err(err) => {
+                return bad_gateway(format!("Error reading the response of {}: {}", url, err));
+            }
+        };
+
+        log::info!(target:"testkit", "{} {} ➡ {}", parts.method, path, status.as_u16());
+        let entry = har_entry(
+            parts.method.as_str(),
+            &url,
+            &headers,
+            &body,
+            status.as_u16(),
+            &response_headers,
+            &response_body,
+        );
+        if let Err(err) = self.record(entry) {
+            log::error!(target:"testkit", "Error writing {}: {}", self.output.display(), err);
+        }
+
+        let mut proxied = Response::new(Full::new(response_body));
+        *proxied.status_mut() = status;
+        *proxied.headers_mut() = response_headers;
+        proxied
+    }
+
+    fn record(&self, entry: Value) -> Result<(), Box<dyn Error>> {
+        let mut entries = self.entries.lock().unwrap();
+        entries.push(entry);
+        let options = HarOptions {
+            skip_assets: true,
+            assert_fields: true,
+            ..Default::default()
+        };
+        let doc = json!({ "log": { "entries": *entries } });
+        let plan = import::har::from_har(&doc, "recorded", &options)?;
+        fs::write(&self.output, import::to_yaml(&plan)?)?;
+        Ok(())
+    }
+}
+
+fn forwarded_headers(headers: &HeaderMap) -> HeaderMap {
+    let mut forwarded = headers.clone();
+    for name in HOP_BY_HOP_HEADERS {
+        forwarded.remove(*name);
+    }
+    forwarded
+}
+
+fn bad_gateway(message: String) -> Response<Full<Bytes>> {
+    log::error!(target:"testkit", "{}", message);
+    let mut response = Response::new(Full::new(Bytes::from(message)));
+    *response.status_mut() = StatusCode::BAD_GATEWAY;
+    response
+}
+
+// har_entry describes an exchange the way a HAR file does
+fn har_entry(
+    method: &str,
+    url: &str,
+    request_headers: &HeaderMap,
+    request_body: &[u8],
+    status: u16,
+    response_headers: &HeaderMap,
+    response_body: &[u8],
+) -> Value {
+    let har_headers = |headers: &HeaderMap| -> Vec<Value> {
+        headers
+            .iter()
+            .map(|(name, value)| {
+                json!({ "name": name.as_str(), "value": String::from_utf8_lossy(value.as_bytes()) })
+            })
+            .collect()
+    };
+    let content_type = |headers: &HeaderMap| {
+        headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default()
+            .to_string()
+    };
+    let mut entry = json!({
+        "request": {
+            "method": method,
+            "url": url,
+            "headers": har_headers(request_headers),
+        },
+        "response": {
+            "status": status,
+            "content": { "mimeType": content_type(response_headers) }
+        }
+    });
+    if !request_body.is_empty() {
+        entry["request"]["postData"] = json!({
+            "mimeType": content_type(request_headers),
+            "text": String::from_utf8_lossy(request_body),
+        });
+    }
+    if let Ok(text) = std::str::from_utf8(response_body) {
+        entry["response"]["content"]["text"] = json!(text);
+    }
+    entry
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::base_request::{Assert, HttpMethod};
+    use hyper::header::HeaderValue;
+
+    #[test]
+    fn test_har_entry() {
+        let mut request_headers = HeaderMap::new();
+        request_headers.insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/json"),
+        );
+        let mut response_headers = HeaderMap::new();
+        response_headers.insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/json"),
+        );
+        let entry = har_entry(
+            "POST",
+            "http://localhost:3000/todos?notify=1",
+            &request_headers,
+            br#"{"task":"run tests"}"#,
+            201,
+            &response_headers,
+            br#"{"id":42,"task":"run tests","tags":[],"owner":null,"done":false}"#,
+        );
+        let options = HarOptions {
+            assert_fields: true,
+            ..Default::default()
+        };
+        let plan = import::har::from_har(
+            &json!({ "log": { "entries": [entry] } }),
+            "recorded",
+            &options,
+        )
+        .unwrap();
+        let item = &plan.steps[0].item;
+        assert_eq!(
+            item.request.http_method,
+            HttpMethod::POST("http://localhost:3000/todos".into())
+        );
+        assert_eq!(item.request.params.as_ref().unwrap()["notify"], "1");
+        assert_eq!(item.request.json, Some(json!({ "task": "run tests" })));
+        assert_eq!(
+            item.asserts,
+            Some(vec![
+                Assert::IsOk("$.resp.status == 201".into()),
+                Assert::IsNumber("$.resp.json.id".into()),
+                Assert::IsString("$.resp.json.task".into()),
+                Assert::IsArray("$.resp.json.tags".into()),
+                Assert::IsNull("$.resp.json.owner".into()),
+                Assert::IsBoolean("$.resp.json.done".into()),
+            ])
+        );
+    }
+}
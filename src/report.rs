@@ -0,0 +1,258 @@
+pub mod html;
+pub mod json;
+pub mod junit;
+
+use crate::{
+    base_cli::Reporter,
+    base_request::{RequestResult, StepStatus},
+};
+use serde::{Serialize, Serializer};
+use std::{fs, io, path::Path, sync::Arc, time::Duration};
+
+// Exit codes used by `testkit test`. Invalid files take precedence over request errors,
+// which take precedence over failed assertions.
+pub const EXIT_OK: i32 = 0;
+pub const EXIT_ASSERTION_FAILURE: i32 = 1;
+pub const EXIT_REQUEST_ERROR: i32 = 2;
+pub const EXIT_INVALID_FILE: i32 = 3;
+// Used by `testkit load` when the results break one of its thresholds
+pub const EXIT_THRESHOLD_FAILURE: i32 = 4;
+
+// FileResult holds the outcome of running a single .tk.yaml file.
+// results is an Err when the file could not be read or parsed into test items.
+#[derive(Debug)]
+pub struct FileResult {
+    pub file: String,
+    pub results: Result<Vec<RequestResult>, String>,
+    pub duration: Duration,
+}
+
+// FileCallback is called with the result of every file as soon as the file finishes.
+pub type FileCallback = Arc<dyn Fn(&FileResult) + Send + Sync>;
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct Summary {
+    pub files: usize,
+    pub invalid_files: usize,
+    pub steps: usize,
+    pub passed: usize,
+    pub failed: usize,
+    pub errored: usize,
+    pub skipped: usize,
+    // Hook steps are counted apart from the steps of the plans
+    pub hooks: usize,
+    pub hooks_failed: usize,
+    pub hooks_errored: usize,
+    #[serde(rename = "duration_ms", serialize_with = "serialize_duration_ms")]
+    pub duration: Duration,
+    // The profile set with --env, if any
+    pub environment: Option<String>,
+}
+
+fn serialize_duration_ms<S: Serializer>(
+    duration: &Duration,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.as_secs_f64() * 1000.0)
+}
+
+impl Summary {
+    pub fn from_results(file_results: &[FileResult], duration: Duration) -> Self {
+        let mut summary = Summary {
+            files: file_results.len(),
+            duration,
+            ..Default::default()
+        };
+        for file_result in file_results {
+            match &file_result.results {
+                Err(_) => summary.invalid_files += 1,
+                Ok(results) => {
+                    for result in results {
+                        if result.hook.is_some() {
+                            summary.hooks += 1;
+                            match result.status() {
+                                StepStatus::Failed => summary.hooks_failed += 1,
+                                StepStatus::Errored => summary.hooks_errored += 1,
+                                _ => {}
+                            }
+                            continue;
+                        }
+                        summary.steps += 1;
+                        match result.status() {
+                            StepStatus::Passed => summary.passed += 1,
+                            StepStatus::Failed => summary.failed += 1,
+                            StepStatus::Errored => summary.errored += 1,
+                            StepStatus::Skipped => summary.skipped += 1,
+                        }
+                    }
+                }
+            }
+        }
+        summary
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        if self.invalid_files > 0 {
+            EXIT_INVALID_FILE
+        } else if self.errored + self.hooks_errored > 0 {
+            EXIT_REQUEST_ERROR
+        } else if self.failed + self.hooks_failed > 0 {
+            EXIT_ASSERTION_FAILURE
+        } else {
+            EXIT_OK
+        }
+    }
+}
+
+pub fn print_summary(file_results: &[FileResult], summary: &Summary) {
+    eprintln!();
+    for file_result in file_results {
+        if let Err(err) = &file_result.results {
+            eprintln!("💥 {}: invalid test file: {}", file_result.file, err);
+        }
+    }
+    if let Some(environment) = &summary.environment {
+        eprint!("Environment: {}  ", environment);
+    }
+    eprint!(
+        "Files: {} ({} invalid)  Steps: {}  ✅ Passed: {}  ❌ Failed: {}  💥 Errored: {}  ⏭  Skipped: {}  ",
+        summary.files,
+        summary.invalid_files,
+        summary.steps,
+        summary.passed,
+        summary.failed,
+        summary.errored,
+        summary.skipped,
+    );
+    if summary.hooks > 0 {
+        eprint!(
+            "Hooks: {} ({} failed, {} errored)  ",
+            summary.hooks, summary.hooks_failed, summary.hooks_errored
+        );
+    }
+    eprintln!("⏱  {:.2?}", summary.duration);
+}
+
+// print_file_log prints the buffered log of a file's steps in one go, so the output of
+// files that ran concurrently doesn't interleave.
+pub fn print_file_log(file_result: &FileResult) {
+    let mut out = format!("\n── {} ──\n", file_result.file);
+    match &file_result.results {
+        Ok(results) => {
+            for result in results {
+                out.push_str(&result.step_log);
+                if !result.step_log.ends_with('\n') {
+                    out.push('\n');
+                }
+            }
+        }
+        Err(err) => {
+            out.push_str(&format!("invalid test file: {}\n", err));
+        }
+    }
+    eprint!("{}", out);
+}
+
+// write_report renders the results with the given reporter, to the output file if one is set
+// or to stdout otherwise.
+pub fn write_report(
+    reporter: Reporter,
+    output: Option<&Path>,
+    file_results: &[FileResult],
+    summary: &Summary,
+) -> io::Result<()> {
+    let report = match reporter {
+        Reporter::Junit => junit::to_junit(file_results, summary),
+        Reporter::Json => json::to_json(file_results, summary),
+        Reporter::Html => html::to_html(file_results, summary),
+        // ndjson events are streamed by json::NdjsonWriter while the tests run
+        Reporter::Ndjson => return Ok(()),
+    };
+    match output {
+        Some(path) => fs::write(path, report),
+        None => {
+            print!("{}", report);
+            Ok(())
+        }
+    }
+}
+
+// escape makes text safe to write into the HTML and JUnit XML reports. Control characters other
+// than tab and newlines aren't allowed in XML 1.0, and are dropped from both.
+fn escape(input: &str) -> String {
+    let mut escaped = String::with_capacity(input.len());
+    for c in input.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c if c.is_control() && !matches!(c, '\t' | '\n' | '\r') => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::base_request::{AssertionResult, Hook};
+
+    fn step(passed: Vec<bool>, error: bool) -> RequestResult {
+        RequestResult {
+            assertions: passed
+                .into_iter()
+                .map(|passed| AssertionResult {
+                    passed,
+                    ..Default::default()
+                })
+                .collect(),
+            step_error: if error { Some("boom".into()) } else { None },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_summary_exit_codes() {
+        let file = |results| FileResult {
+            file: "file.tk.yaml".into(),
+            results,
+            duration: Duration::ZERO,
+        };
+        let passing = vec![file(Ok(vec![step(vec![true], false)]))];
+        let summary = Summary::from_results(&passing, Duration::ZERO);
+        assert_eq!(summary.passed, 1);
+        assert_eq!(summary.exit_code(), EXIT_OK);
+
+        let failing = vec![file(Ok(vec![
+            step(vec![true, false], false),
+            step(vec![], false),
+        ]))];
+        let summary = Summary::from_results(&failing, Duration::ZERO);
+        assert_eq!((summary.steps, summary.failed), (2, 1));
+        assert_eq!(summary.exit_code(), EXIT_ASSERTION_FAILURE);
+
+        let erroring = vec![file(Ok(vec![step(vec![false], false), step(vec![], true)]))];
+        let summary = Summary::from_results(&erroring, Duration::ZERO);
+        assert_eq!(summary.errored, 1);
+        assert_eq!(summary.exit_code(), EXIT_REQUEST_ERROR);
+
+        let invalid = vec![
+            file(Ok(vec![step(vec![], true)])),
+            file(Err("bad yaml".into())),
+        ];
+        let summary = Summary::from_results(&invalid, Duration::ZERO);
+        assert_eq!(summary.invalid_files, 1);
+        assert_eq!(summary.exit_code(), EXIT_INVALID_FILE);
+
+        let mut cleanup = step(vec![false], false);
+        cleanup.hook = Some(Hook::AfterAll);
+        let failing_hook = vec![file(Ok(vec![step(vec![true], false), cleanup]))];
+        let summary = Summary::from_results(&failing_hook, Duration::ZERO);
+        assert_eq!((summary.steps, summary.passed), (1, 1));
+        assert_eq!((summary.hooks, summary.hooks_failed), (1, 1));
+        assert_eq!(summary.exit_code(), EXIT_ASSERTION_FAILURE);
+    }
+}
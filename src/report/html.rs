@@ -0,0 +1,251 @@
+use super::{FileResult, Summary, escape};
+use crate::base_request::{RequestResult, StepStatus};
+use serde_json::Value;
+use std::fmt::Write;
+
+const STYLE: &str = r#"
+body { font-family: -apple-system, BlinkMacSystemFont, "Segoe UI", Helvetica, Arial, sans-serif; margin: 2rem auto; max-width: 1100px; padding: 0 1rem; color: #1f2328; }
+h1 { font-size: 1.6rem; }
+h2 { font-size: 1.2rem; margin-top: 2rem; border-bottom: 1px solid #d0d7de; padding-bottom: .3rem; }
+h3 { font-size: .95rem; margin: 1rem 0 .4rem; }
+pre { background: #f6f8fa; padding: .75rem; overflow-x: auto; border-radius: 6px; font-size: .85rem; white-space: pre-wrap; word-break: break-all; }
+code { font-size: .85rem; }
+.summary span { margin-right: 1.2rem; }
+details.step { border: 1px solid #d0d7de; border-radius: 6px; margin: .5rem 0; padding: .4rem .8rem; }
+details.step > summary { cursor: pointer; font-weight: 600; }
+.badge { display: inline-block; min-width: 4.5rem; text-align: center; border-radius: 4px; padding: 0 .4rem; margin-right: .5rem; color: #fff; font-size: .8rem; }
+.passed .badge, .badge.passed { background: #1a7f37; }
+.failed .badge, .badge.failed { background: #cf222e; }
+.errored .badge, .badge.errored { background: #9a6700; }
+.skipped .badge, .badge.skipped { background: #6e7781; }
+.time { color: #6e7781; font-weight: normal; float: right; }
+ul.asserts { list-style: none; padding-left: 0; }
+ul.asserts li { margin: .25rem 0; }
+.advice { color: #cf222e; margin-left: 1.6rem; font-size: .85rem; }
+.error { color: #cf222e; }
+"#;
+
+// to_html renders the results of a run as a single self-contained HTML page.
+// Failed and errored steps are expanded by default.
+pub fn to_html(file_results: &[FileResult], summary: &Summary) -> String {
+    let mut out = String::new();
+    out.push_str("<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n");
+    out.push_str("<title>testkit report</title>\n");
+    let _ = writeln!(out, "<style>{}</style>\n</head>\n<body>", STYLE);
+    out.push_str("<h1>testkit report</h1>\n");
+    if let Some(environment) = &summary.environment {
+        let _ = writeln!(
+            out,
+            r#"<p class="summary"><span>Environment: {}</span></p>"#,
+            escape(environment)
+        );
+    }
+    let _ = writeln!(
+        out,
+        r#"<p class="summary"><span>Files: {} ({} invalid)</span><span>Steps: {}</span><span class="badge passed">{} passed</span><span class="badge failed">{} failed</span><span class="badge errored">{} errored</span><span class="badge skipped">{} skipped</span><span>{:.2?}</span></p>"#,
+        summary.files,
+        summary.invalid_files,
+        summary.steps,
+        summary.passed,
+        summary.failed,
+        summary.errored,
+        summary.skipped,
+        summary.duration
+    );
+    if summary.hooks > 0 {
+        let _ = writeln!(
+            out,
+            r#"<p class="summary"><span>Hooks: {}</span><span class="badge failed">{} failed</span><span class="badge errored">{} errored</span></p>"#,
+            summary.hooks, summary.hooks_failed, summary.hooks_errored
+        );
+    }
+    for file_result in file_results {
+        write_file(&mut out, file_result);
+    }
+    out.push_str("</body>\n</html>\n");
+    out
+}
+
+fn write_file(out: &mut String, file_result: &FileResult) {
+    let _ = writeln!(
+        out,
+        r#"<section class="file">
+<h2>{} <span class="time">{:.2?}</span></h2>"#,
+        escape(&file_result.file),
+        file_result.duration
+    );
+    match &file_result.results {
+        Err(err) => {
+            let _ = writeln!(
+                out,
+                r#"<pre class="error">Invalid test file: {}</pre>"#,
+                escape(err)
+            );
+        }
+        Ok(results) => {
+            for result in results.iter().filter(|r| r.hook.is_none()) {
+                write_step(out, result);
+            }
+            if results.iter().any(|r| r.hook.is_some()) {
+                out.push_str("<h3>Hooks</h3>\n");
+            }
+            for result in results.iter().filter(|r| r.hook.is_some()) {
+                write_step(out, result);
+            }
+        }
+    }
+    out.push_str("</section>\n");
+}
+
+fn write_step(out: &mut String, result: &RequestResult) {
+    let status = result.status();
+    let (class, label) = match status {
+        StepStatus::Passed => ("passed", "PASSED"),
+        StepStatus::Failed => ("failed", "FAILED"),
+        StepStatus::Errored => ("errored", "ERROR"),
+        StepStatus::Skipped => ("skipped", "SKIPPED"),
+    };
+    let open = matches!(status, StepStatus::Failed | StepStatus::Errored);
+    let mut name = result
+        .step_name
+        .clone()
+        .unwrap_or_else(|| format!("step {}", result.step_index));
+    if let Some(hook) = result.hook {
+        name = format!("{}: {}", hook.name(), name);
+    }
+    let _ = writeln!(
+        out,
+        r#"<details class="step {class}"{}>
+<summary><span class="badge">{label}</span>{} <span class="time">{:.1}ms</span></summary>"#,
+        if open { " open" } else { "" },
+        escape(&name),
+        result.timings.total_ms
+    );
+
+    if let Some(err) = &result.step_error {
+        let _ = writeln!(out, r#"<pre class="error">{}</pre>"#, escape(err));
+    }
+    if status == StepStatus::Skipped {
+        let _ = writeln!(out, "<p>{}</p>", escape(result.step_log.trim()));
+    }
+
+    if !result.assertions.is_empty() {
+        out.push_str("<h3>Assertions</h3>\n<ul class=\"asserts\">\n");
+        for assertion in &result.assertions {
+            let icon = if assertion.passed && assertion.error.is_none() {
+                "✅"
+            } else {
+                "❌"
+            };
+            let _ = write!(
+                out,
+                "<li>{} <code>{}</code> <code>{}</code>",
+                icon,
+                escape(&assertion.kind),
+                escape(&assertion.expr)
+            );
+            if let Some(eval_expr) = &assertion.eval_expr {
+                if eval_expr != &assertion.expr {
+                    let _ = write!(out, " ⮕ <code>{}</code>", escape(eval_expr));
+                }
+            }
+            if let Some(advice) = assertion.error.as_ref().and_then(|e| e.advice()) {
+                let _ = write!(out, r#"<div class="advice">{}</div>"#, escape(advice));
+            }
+            out.push_str("</li>\n");
+        }
+        out.push_str("</ul>\n");
+    }
+
+    if let Some(req) = &result.resolved_request {
+        let mut text = format!("{} {}\n", req.method, req.url);
+        for header in &req.headers {
+            let _ = writeln!(text, "{}: {}", header.name, header.value);
+        }
+        if let Some(body) = &req.body {
+            text.push('\n');
+            text.push_str(&pretty_body(body));
+        }
+        let _ = writeln!(out, "<h3>Request</h3>\n<pre>{}</pre>", escape(&text));
+    }
+
+    // The response is only set when a request went through
+    let resp = &result.request.resp;
+    if resp.status != 0 {
+        let mut text = format!("{}\n", resp.status);
+        if let Value::Object(headers) = &resp.headers {
+            for (name, values) in headers {
+                let values = match values {
+                    Value::Array(values) => values
+                        .iter()
+                        .map(|v| v.as_str().unwrap_or_default())
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                    v => v.to_string(),
+                };
+                let _ = writeln!(text, "{}: {}", name, values);
+            }
+        }
+        if !resp.raw.is_empty() {
+            text.push('\n');
+            text.push_str(&pretty_body(&resp.raw));
+        }
+        let _ = writeln!(out, "<h3>Response</h3>\n<pre>{}</pre>", escape(&text));
+    }
+    out.push_str("</details>\n");
+}
+
+fn pretty_body(body: &str) -> String {
+    match serde_json::from_str::<Value>(body) {
+        Ok(json) => serde_json::to_string_pretty(&json).unwrap_or_else(|_| body.to_string()),
+        Err(_) => body.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::base_request::AssertionResult;
+    use std::time::Duration;
+
+    #[test]
+    fn test_to_html() {
+        let mut failed = RequestResult {
+            step_name: Some("<script>".into()),
+            assertions: vec![AssertionResult {
+                kind: "ok".into(),
+                expr: "$.resp.status == 201".into(),
+                eval_expr: Some("200 == 201".into()),
+                passed: false,
+                error: None,
+            }],
+            ..Default::default()
+        };
+        failed.request.resp.status = 200;
+        failed.request.resp.raw = r#"{"id":1}"#.into();
+        let passed = RequestResult {
+            step_name: Some("ok step".into()),
+            step_index: 1,
+            assertions: vec![AssertionResult {
+                kind: "ok".into(),
+                expr: "$.resp.status == 200".into(),
+                passed: true,
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let file_results = vec![FileResult {
+            file: "todos.tk.yaml".into(),
+            results: Ok(vec![failed, passed]),
+            duration: Duration::ZERO,
+        }];
+        let summary = Summary::from_results(&file_results, Duration::ZERO);
+        let html = to_html(&file_results, &summary);
+
+        assert!(html.contains(r#"<details class="step failed" open>"#));
+        assert!(html.contains(r#"<details class="step passed">"#));
+        assert!(html.contains("&lt;script&gt;"));
+        assert!(html.contains("⮕ <code>200 == 201</code>"));
+        assert!(html.contains("&quot;id&quot;: 1"));
+    }
+}
@@ -0,0 +1,217 @@
+use super::{FileResult, Summary};
+use crate::base_request::{
+    AssertionResult, Attempt, Hook, RequestAndResponse, RequestResult, ResolvedRequest, StepStatus,
+    StepTimings,
+};
+use serde::Serialize;
+use std::{
+    fs::File,
+    io::{self, Write},
+    path::Path,
+    sync::Mutex,
+};
+
+// SCHEMA_VERSION is bumped whenever a field is removed or changes meaning.
+// Adding new fields doesn't change the version. See docs/json-report.md
+pub const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Serialize)]
+pub struct JsonReport<'a> {
+    pub schema_version: u32,
+    pub testkit_version: &'static str,
+    pub summary: &'a Summary,
+    pub files: Vec<FileReport<'a>>,
+}
+
+#[derive(Serialize)]
+pub struct FileReport<'a> {
+    pub file: &'a str,
+    pub duration_ms: f64,
+    pub error: Option<&'a str>,
+    pub steps: Vec<StepReport<'a>>,
+    pub hooks: Vec<StepReport<'a>>,
+}
+
+#[derive(Serialize)]
+pub struct StepReport<'a> {
+    pub step_index: u32,
+    pub step_name: Option<&'a str>,
+    pub hook: Option<Hook>,
+    pub status: StepStatus,
+    pub error: Option<&'a str>,
+    pub timings: &'a StepTimings,
+    pub assertions: &'a [AssertionResult],
+    pub resolved_request: Option<&'a ResolvedRequest>,
+    pub attempts: &'a [Attempt],
+    pub request: &'a RequestAndResponse,
+    pub log: &'a str,
+}
+
+impl<'a> FileReport<'a> {
+    pub fn new(file_result: &'a FileResult) -> Self {
+        let (results, error) = match &file_result.results {
+            Ok(results) => (results.as_slice(), None),
+            Err(err) => (&[][..], Some(err.as_str())),
+        };
+        let (hooks, steps) = results.iter().partition(|r| r.hook.is_some());
+        FileReport {
+            file: &file_result.file,
+            duration_ms: file_result.duration.as_secs_f64() * 1000.0,
+            error,
+            steps: reports(steps),
+            hooks: reports(hooks),
+        }
+    }
+}
+
+impl<'a> StepReport<'a> {
+    pub fn new(result: &'a RequestResult) -> Self {
+        StepReport {
+            step_index: result.step_index,
+            step_name: result.step_name.as_deref(),
+            hook: result.hook,
+            status: result.status(),
+            error: result.step_error.as_deref(),
+            timings: &result.timings,
+            assertions: &result.assertions,
+            resolved_request: result.resolved_request.as_ref(),
+            attempts: &result.attempts,
+            request: &result.request,
+            log: &result.step_log,
+        }
+    }
+}
+
+fn reports(results: Vec<&RequestResult>) -> Vec<StepReport<'_>> {
+    results.into_iter().map(StepReport::new).collect()
+}
+
+pub fn to_json(file_results: &[FileResult], summary: &Summary) -> String {
+    let report = JsonReport {
+        schema_version: SCHEMA_VERSION,
+        testkit_version: env!("CARGO_PKG_VERSION"),
+        summary,
+        files: file_results.iter().map(FileReport::new).collect(),
+    };
+    serde_json::to_string_pretty(&report).unwrap_or_default() + "\n"
+}
+
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum Event<'a> {
+    Step {
+        file: &'a str,
+        #[serde(flatten)]
+        step: StepReport<'a>,
+    },
+    File {
+        file: &'a str,
+        duration_ms: f64,
+        error: Option<&'a str>,
+    },
+    Summary {
+        testkit_version: &'static str,
+        #[serde(flatten)]
+        summary: &'a Summary,
+    },
+}
+
+#[derive(Serialize)]
+struct EventLine<'a> {
+    schema_version: u32,
+    #[serde(flatten)]
+    event: Event<'a>,
+}
+
+// NdjsonWriter streams one JSON event per line as the run progresses:
+// a `step` event when a step finishes, a `file` event when a file finishes
+// and a single `summary` event at the end of the run.
+pub struct NdjsonWriter {
+    out: Mutex<Box<dyn Write + Send>>,
+}
+
+impl NdjsonWriter {
+    pub fn new(output: Option<&Path>) -> io::Result<Self> {
+        let out: Box<dyn Write + Send> = match output {
+            Some(path) => Box::new(File::create(path)?),
+            None => Box::new(io::stdout()),
+        };
+        Ok(NdjsonWriter {
+            out: Mutex::new(out),
+        })
+    }
+
+    pub fn step(&self, file: &str, result: &RequestResult) {
+        self.write(Event::Step {
+            file,
+            step: StepReport::new(result),
+        })
+    }
+
+    pub fn file(&self, file_result: &FileResult) {
+        self.write(Event::File {
+            file: &file_result.file,
+            duration_ms: file_result.duration.as_secs_f64() * 1000.0,
+            error: file_result.results.as_ref().err().map(|e| e.as_str()),
+        })
+    }
+
+    pub fn summary(&self, summary: &Summary) {
+        self.write(Event::Summary {
+            testkit_version: env!("CARGO_PKG_VERSION"),
+            summary,
+        })
+    }
+
+    fn write(&self, event: Event) {
+        let line = EventLine {
+            schema_version: SCHEMA_VERSION,
+            event,
+        };
+        let Ok(json) = serde_json::to_string(&line) else {
+            return;
+        };
+        if let Ok(mut out) = self.out.lock() {
+            if let Err(err) = writeln!(out, "{}", json).and_then(|_| out.flush()) {
+                log::error!(target:"testkit", "Error writing ndjson event: {}", err);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_to_json() {
+        let file_results = vec![FileResult {
+            file: "todos.tk.yaml".into(),
+            results: Ok(vec![RequestResult {
+                step_name: Some("create todo".into()),
+                assertions: vec![AssertionResult {
+                    kind: "ok".into(),
+                    expr: "$.resp.status == 201".into(),
+                    eval_expr: Some("200 == 201".into()),
+                    passed: false,
+                    error: None,
+                }],
+                ..Default::default()
+            }]),
+            duration: Duration::from_millis(5),
+        }];
+        let summary = Summary::from_results(&file_results, Duration::from_millis(5));
+        let report: serde_json::Value =
+            serde_json::from_str(&to_json(&file_results, &summary)).unwrap();
+
+        assert_eq!(report["schema_version"], SCHEMA_VERSION);
+        assert_eq!(report["summary"]["failed"], 1);
+        assert_eq!(report["summary"]["duration_ms"], 5.0);
+        let step = &report["files"][0]["steps"][0];
+        assert_eq!(step["status"], "failed");
+        assert_eq!(step["assertions"][0]["kind"], "ok");
+        assert_eq!(step["assertions"][0]["eval_expr"], "200 == 201");
+        assert_eq!(step["assertions"][0]["passed"], false);
+    }
+}
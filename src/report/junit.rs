@@ -0,0 +1,227 @@
+use super::{FileResult, Summary, escape};
+use crate::base_request::{RequestResult, StepStatus};
+use std::fmt::Write;
+
+// to_junit renders the results of a run as JUnit XML.
+// Each file becomes a <testsuite> and each step a <testcase>. The hook steps of a file are
+// written to a suite of their own, named after the file with ` hooks` appended.
+pub fn to_junit(file_results: &[FileResult], summary: &Summary) -> String {
+    let mut out = String::new();
+    out.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    let _ = writeln!(
+        out,
+        r#"<testsuites name="testkit" tests="{}" failures="{}" errors="{}" skipped="{}" time="{:.3}">"#,
+        // Invalid files are written as a `parse` testcase with an error
+        summary.steps + summary.hooks + summary.invalid_files,
+        summary.failed + summary.hooks_failed,
+        summary.errored + summary.hooks_errored + summary.invalid_files,
+        summary.skipped,
+        summary.duration.as_secs_f64()
+    );
+    // The environment is written into every suite, as JUnit only has properties on suites
+    let properties = summary
+        .environment
+        .as_ref()
+        .map(|env| {
+            format!(
+                "    <properties>\n      <property name=\"environment\" value=\"{}\"/>\n    </properties>\n",
+                escape(env)
+            )
+        })
+        .unwrap_or_default();
+    for file_result in file_results {
+        write_testsuite(&mut out, file_result, &properties);
+    }
+    out.push_str("</testsuites>\n");
+    out
+}
+
+fn write_testsuite(out: &mut String, file_result: &FileResult, properties: &str) {
+    let file = escape(&file_result.file);
+    let time = file_result.duration.as_secs_f64();
+    let results = match &file_result.results {
+        Ok(results) => results,
+        Err(err) => {
+            // An invalid file is reported as a suite with a single errored testcase,
+            // so it isn't silently dropped by CI dashboards.
+            let _ = writeln!(
+                out,
+                r#"  <testsuite name="{file}" tests="1" failures="0" errors="1" skipped="0" time="{time:.3}">"#
+            );
+            out.push_str(properties);
+            let _ = writeln!(out, r#"    <testcase name="parse" classname="{file}">"#);
+            let _ = writeln!(
+                out,
+                r#"      <error message="invalid test file" type="InvalidTestFile">{}</error>"#,
+                escape(err)
+            );
+            out.push_str("    </testcase>\n  </testsuite>\n");
+            return;
+        }
+    };
+
+    let (hooks, steps): (Vec<_>, Vec<_>) = results.iter().partition(|r| r.hook.is_some());
+    write_results(out, &file, &steps, time, properties);
+    if !hooks.is_empty() {
+        let time = hooks.iter().map(|r| r.timings.total_ms).sum::<f64>() / 1000.0;
+        write_results(out, &format!("{} hooks", file), &hooks, time, properties);
+    }
+}
+
+fn write_results(
+    out: &mut String,
+    name: &str,
+    results: &[&RequestResult],
+    time: f64,
+    properties: &str,
+) {
+    let count = |status| results.iter().filter(|r| r.status() == status).count();
+    let _ = writeln!(
+        out,
+        r#"  <testsuite name="{name}" tests="{}" failures="{}" errors="{}" skipped="{}" time="{time:.3}">"#,
+        results.len(),
+        count(StepStatus::Failed),
+        count(StepStatus::Errored),
+        count(StepStatus::Skipped),
+    );
+    out.push_str(properties);
+    for result in results {
+        write_testcase(out, name, result);
+    }
+    out.push_str("  </testsuite>\n");
+}
+
+fn write_testcase(out: &mut String, classname: &str, result: &RequestResult) {
+    let _ = writeln!(
+        out,
+        r#"    <testcase name="{}" classname="{}" time="{:.3}">"#,
+        escape(&step_name(result)),
+        classname,
+        result.timings.total_ms / 1000.0
+    );
+    match result.status() {
+        StepStatus::Passed => {}
+        StepStatus::Skipped => out.push_str("      <skipped/>\n"),
+        StepStatus::Errored => {
+            let err = escape(result.step_error.as_deref().unwrap_or_default());
+            let _ = writeln!(
+                out,
+                r#"      <error message="{err}" type="RequestError">{err}</error>"#
+            );
+        }
+        StepStatus::Failed => {
+            let mut failures = Vec::new();
+            for assertion in &result.assertions {
+                let name = format!("{}: {}", assertion.kind, assertion.expr);
+                match &assertion.error {
+                    None if assertion.passed => {}
+                    None => failures.push(format!("{} evaluated to false", name)),
+                    Some(err) => failures.push(format!(
+                        "{}: {}",
+                        name,
+                        err.advice().unwrap_or("could not be evaluated")
+                    )),
+                }
+            }
+            let _ = writeln!(
+                out,
+                r#"      <failure message="{} of {} assertions failed" type="AssertionError">{}</failure>"#,
+                failures.len(),
+                result.assertions.len(),
+                escape(&failures.join("\n"))
+            );
+        }
+    }
+    if !result.step_log.is_empty() {
+        let _ = writeln!(
+            out,
+            "      <system-out>{}</system-out>",
+            escape(&result.step_log)
+        );
+    }
+    out.push_str("    </testcase>\n");
+}
+
+fn step_name(result: &RequestResult) -> String {
+    let name = result
+        .step_name
+        .clone()
+        .unwrap_or_else(|| format!("step {}", result.step_index));
+    match result.hook {
+        Some(hook) => format!("{}: {}", hook.name(), name),
+        None => name,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::base_request::AssertionResult;
+    use std::time::Duration;
+
+    #[test]
+    fn test_to_junit() {
+        let file_results = vec![
+            FileResult {
+                file: "todos.tk.yaml".into(),
+                results: Ok(vec![
+                    RequestResult {
+                        step_name: Some("create <todo>".into()),
+                        assertions: vec![
+                            AssertionResult {
+                                kind: "ok".into(),
+                                expr: "$.resp.status == 201".into(),
+                                passed: true,
+                                ..Default::default()
+                            },
+                            AssertionResult {
+                                kind: "exists".into(),
+                                expr: "$.resp.json.id".into(),
+                                ..Default::default()
+                            },
+                        ],
+                        ..Default::default()
+                    },
+                    RequestResult {
+                        step_index: 1,
+                        step_error: Some("Error sending request".into()),
+                        ..Default::default()
+                    },
+                    RequestResult {
+                        step_index: 2,
+                        skipped: true,
+                        ..Default::default()
+                    },
+                ]),
+                duration: Duration::from_millis(20),
+            },
+            FileResult {
+                file: "broken.tk.yaml".into(),
+                results: Err("bad yaml".into()),
+                duration: Duration::ZERO,
+            },
+        ];
+        let mut summary = Summary::from_results(&file_results, Duration::from_millis(20));
+        summary.environment = Some("staging".into());
+        let xml = to_junit(&file_results, &summary);
+
+        assert!(xml.contains(
+            r#"<testsuites name="testkit" tests="4" failures="1" errors="2" skipped="1" time="0.020">"#
+        ));
+        assert!(xml.contains(
+            r#"<testcase name="create &lt;todo&gt;" classname="todos.tk.yaml" time="0.000">"#
+        ));
+        assert!(xml.contains(r#"<failure message="1 of 2 assertions failed" type="AssertionError">exists: $.resp.json.id evaluated to false</failure>"#));
+        assert!(xml.contains(r#"<testcase name="step 1" classname="todos.tk.yaml" time="0.000">"#));
+        assert!(xml.contains(r#"<error message="Error sending request" type="RequestError">"#));
+        assert!(xml.contains("<skipped/>"));
+        assert_eq!(
+            xml.matches(r#"<property name="environment" value="staging"/>"#)
+                .count(),
+            2
+        );
+        assert!(xml.contains(
+            r#"<error message="invalid test file" type="InvalidTestFile">bad yaml</error>"#
+        ));
+    }
+}
@@ -0,0 +1,304 @@
+use crate::base_request::{ASSERT_KINDS, HTTP_METHODS};
+use serde_json::{Map, Value, json};
+
+// tk_schema is the JSON Schema of a .tk.yaml test file, so editors can autocomplete and check it.
+// It mirrors the serde names of TestItem, RequestConfig, HttpMethod and Assert.
+pub fn tk_schema() -> Value {
+    let string_map = json!({
+        "type": "object",
+        "additionalProperties": { "type": "string" }
+    });
+
+    let mut step_properties = Map::new();
+    step_properties.insert(
+        "title".into(),
+        json!({ "type": "string", "description": "The name of the step, shown in logs and reports." }),
+    );
+    for method in HTTP_METHODS {
+        step_properties.insert(
+            method.to_string(),
+            json!({
+                "type": "string",
+                "description": format!("Sends a {} request to the given url.", method)
+            }),
+        );
+    }
+    let mut request_properties = json!({
+        "method": {
+            "type": "string",
+            "description": "Any other HTTP method, eg `PURGE` or `PROPFIND`. Sent to `url`."
+        },
+        "url": { "type": "string", "description": "The request url of a step with `method`." },
+        "headers": with_description(&string_map, "Request headers."),
+        "json": {
+            "description": "A JSON request body. Also sets the `Content-Type: application/json` header."
+        },
+        "params": with_description(&string_map, "Query parameters, added to the url."),
+        "requestBody": with_description(&string_map, "A request body of string fields."),
+        "raw": { "type": "string", "description": "A raw request body." },
+        "httpVersion": {
+            "type": "string",
+            "description": "Set to `http-2` to send the request over HTTP/2 with prior knowledge."
+        },
+        "timeout": {
+            "type": "integer",
+            "minimum": 0,
+            "description": "Request timeout in seconds."
+        },
+        "follow_redirects": {
+            "type": "boolean",
+            "description": "Set to false to stop at the first redirect instead of following it."
+        },
+        "max_redirects": {
+            "type": "integer",
+            "minimum": 0,
+            "description": "The most redirects that are followed before the step errors, 10 by default."
+        },
+        "ignore_ssl_errors": {
+            "type": "boolean",
+            "description": "Accepts invalid TLS certificates."
+        },
+        "disabled": { "type": "boolean", "description": "Skips the step." },
+        "dump": {
+            "type": "boolean",
+            "description": "Prints the request and response that assertions are evaluated against."
+        },
+        "asserts": {
+            "type": "array",
+            "description": "Assertions on the response, evaluated in order.",
+            "items": { "$ref": "#/definitions/assert" }
+        },
+        "exports": with_description(
+            &string_map,
+            "Values to capture from the response, as jsonpaths. Later steps reference them as `{{name}}`."
+        ),
+        "tags": {
+            "type": "array",
+            "items": { "type": "string" },
+            "description": "Tags to select the step with `--tag` and `--exclude-tag`."
+        },
+        "if": {
+            "type": "string",
+            "description": "Only runs the step when this expression holds. Evaluated before the request, against the exports so far and `$.env`, eg `{{createdId}} != null`."
+        },
+        "skip_if": {
+            "type": "string",
+            "description": "Skips the step when this expression holds, eg `$.env.FEATURE_X != \"on\"`."
+        },
+        "for_each": {
+            "description": "Runs the step once per row, with the columns of the row as `{{column}}` variables. A list of rows, or the path of a CSV, JSON or YAML file relative to the test file.",
+            "oneOf": [
+                { "type": "string" },
+                { "type": "array", "items": { "type": "object" } }
+            ]
+        },
+        "retry": {
+            "type": "object",
+            "description": "Re-sends the step until `until` holds, or without `until`, until the step passes. The asserts are evaluated once the retries are over.",
+            "properties": {
+                "attempts": {
+                    "type": "integer",
+                    "minimum": 1,
+                    "description": "How many times the step is sent at most. Defaults to 3."
+                },
+                "delay_ms": {
+                    "type": "integer",
+                    "minimum": 0,
+                    "description": "Milliseconds to wait between attempts. Defaults to 1000."
+                },
+                "backoff": {
+                    "enum": ["constant", "exponential"],
+                    "description": "`exponential` doubles the delay after every attempt. Defaults to `constant`."
+                },
+                "until": {
+                    "type": "string",
+                    "description": "An expression like the `ok` assertion, eg `$.resp.json.status == \"done\"`."
+                }
+            },
+            "additionalProperties": false
+        }
+    });
+    step_properties.append(request_properties.as_object_mut().unwrap());
+
+    let assert_properties: Map<String, Value> = ASSERT_KINDS
+        .iter()
+        .map(|kind| {
+            (
+                kind.to_string(),
+                json!({ "type": "string", "description": assert_description(kind) }),
+            )
+        })
+        .collect();
+
+    let steps_ref = json!({ "$ref": "#/definitions/steps" });
+    json!({
+        "$schema": "http://json-schema.org/draft-07/schema#",
+        "title": "testkit test file",
+        "description": "A list of steps, each sending one HTTP request and asserting on the response, or a plan with hooks around its steps.",
+        "oneOf": [
+            steps_ref,
+            {
+                "type": "object",
+                "properties": {
+                    "before_all": with_description(&steps_ref, "Steps that run once, before the steps of the plan. The steps are skipped when one of them fails."),
+                    "before_each": with_description(&steps_ref, "Steps that run before every step. The step is skipped when one of them fails."),
+                    "steps": with_description(&steps_ref, "The steps of the plan."),
+                    "after_each": with_description(&steps_ref, "Steps that run after every step, even when it fails."),
+                    "after_all": with_description(&steps_ref, "Steps that run once, after the steps of the plan, even when they fail or error out.")
+                },
+                "required": ["steps"],
+                "additionalProperties": false
+            }
+        ],
+        "definitions": {
+            "steps": {
+                "type": "array",
+                "items": {
+                    "anyOf": [
+                        { "$ref": "#/definitions/step" },
+                        { "$ref": "#/definitions/include" }
+                    ]
+                }
+            },
+            "step": {
+                "type": "object",
+                "properties": step_properties,
+                "additionalProperties": false,
+                "oneOf": HTTP_METHODS
+                    .iter()
+                    .map(|method| json!({ "required": [method] }))
+                    .chain([json!({ "required": ["method", "url"] })])
+                    .collect::<Vec<_>>()
+            },
+            "include": {
+                "type": "object",
+                "description": "Runs the steps of another test file in place of this one.",
+                "properties": {
+                    "include": {
+                        "type": "string",
+                        "description": "The path of the test file, relative to this one."
+                    },
+                    "with": {
+                        "type": "object",
+                        "description": "Variables for the included steps, eg `{ user: admin }`."
+                    }
+                },
+                "required": ["include"],
+                "additionalProperties": false
+            },
+            "assert": {
+                "type": "object",
+                "properties": assert_properties,
+                "additionalProperties": false,
+                "minProperties": 1,
+                "maxProperties": 1
+            }
+        }
+    })
+}
+
+// assert_description documents an assertion kind, one of ASSERT_KINDS
+fn assert_description(kind: &str) -> &'static str {
+    match kind {
+        "ok" => "An expression that must evaluate to true, eg `$.resp.status == 200`.",
+        "array" => "A jsonpath that must point at an array.",
+        "empty" => "A jsonpath that must point at an empty string or array.",
+        "string" => "A jsonpath that must point at a string.",
+        "number" => "A jsonpath that must point at a number.",
+        "boolean" => "A jsonpath that must point at a boolean.",
+        "null" => "A jsonpath that must point at null.",
+        "exists" => "A jsonpath that must point at a value.",
+        "date" => {
+            "A jsonpath followed by a chrono date format, eg `$.resp.json.created_at %Y-%m-%d`."
+        }
+        "notEmpty" => "A jsonpath that must point at a non empty string or array.",
+        "contains" => "`<jsonpath> ~ <value>`. The string or array must contain the value.",
+        "notContains" => "`<jsonpath> ~ <value>`. The string or array must not contain the value.",
+        "regexMatch" => "`<jsonpath> ~ <regex>`. The string must match the regex.",
+        "noRegexMatch" => "`<jsonpath> ~ <regex>`. The string must not match the regex.",
+        _ => "",
+    }
+}
+
+fn with_description(schema: &Value, description: &str) -> Value {
+    let mut schema = schema.clone();
+    schema["description"] = json!(description);
+    schema
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::base_request::{STEP_KEYS, TestItem, TestPlan};
+
+    #[test]
+    fn test_tk_schema() {
+        let schema = tk_schema();
+
+        // Every key of a fully populated step must be in the schema
+        let item: TestItem = serde_yaml::from_str(
+            r#"
+title: all keys
+POST: http://localhost:3000
+headers: {}
+json: {}
+params: {}
+disabled: false
+httpVersion: http-2
+timeout: 5
+follow_redirects: true
+max_redirects: 3
+ignore_ssl_errors: true
+raw: ""
+requestBody: {}
+dump: true
+asserts:
+  - noRegexMatch: $.resp.json.name ~ ^x
+exports: {}
+tags: []
+retry: {}
+if: "true"
+skip_if: "false"
+for_each: []
+"#,
+        )
+        .unwrap();
+        let mut step = serde_json::to_value(&item).unwrap();
+        step.as_object_mut().unwrap().retain(|_, v| !v.is_null());
+        let properties = &schema["definitions"]["step"]["properties"];
+        for key in step.as_object().unwrap().keys() {
+            assert!(properties.get(key).is_some(), "{} missing from schema", key);
+        }
+        // and the schema has the keys the validator accepts, no more
+        let mut keys: Vec<&str> = STEP_KEYS.iter().chain(HTTP_METHODS).copied().collect();
+        let mut schema_keys: Vec<&str> = properties
+            .as_object()
+            .unwrap()
+            .keys()
+            .map(String::as_str)
+            .collect();
+        keys.sort();
+        schema_keys.sort();
+        assert_eq!(schema_keys, keys);
+        for kind in ASSERT_KINDS {
+            assert!(
+                !assert_description(kind).is_empty(),
+                "{} has no description",
+                kind
+            );
+        }
+
+        // The published schema must be regenerated with `testkit schema` when the format changes
+        let published: Value =
+            serde_json::from_str(include_str!("../docs/tk.schema.json")).unwrap();
+        assert_eq!(published, schema);
+
+        let include = &schema["definitions"]["include"]["properties"];
+        assert!(include.get("include").is_some() && include.get("with").is_some());
+
+        let plan = &schema["oneOf"][1]["properties"];
+        for (key, _) in TestPlan::<TestItem>::default().sections() {
+            assert!(plan.get(key).is_some(), "{} missing from schema", key);
+        }
+    }
+}
@@ -0,0 +1,641 @@
+use crate::{
+    base_request::{
+        ASSERT_KINDS, Assert, HTTP_METHODS, HttpMethod, STEP_KEYS, TestItem, TestPlan,
+        find_all_jsonpaths, get_vars, report_error,
+    },
+    data, include,
+};
+use jsonpath_lib::Compiled;
+use miette::{Diagnostic, NamedSource, SourceSpan};
+use regex::Regex;
+use serde_yaml::Value as YamlValue;
+use std::{collections::HashSet, fs, ops::Range, path::PathBuf};
+use thiserror::Error;
+
+// The keys of a plan with hooks, in the order they run
+const PLAN_KEYS: &[&str] = &[
+    "before_all",
+    "before_each",
+    "steps",
+    "after_each",
+    "after_all",
+];
+
+// ValidationError is a problem found in a test file without running it.
+#[derive(Error, Debug, Diagnostic)]
+#[error("{message}")]
+#[diagnostic(severity(error))]
+pub struct ValidationError {
+    pub message: String,
+    #[help]
+    pub advice: Option<String>,
+    #[source_code]
+    src: NamedSource<String>,
+    #[label("here")]
+    bad_bit: SourceSpan,
+}
+
+// validate_files checks every file and prints the problems it finds. vars are the names set
+// with --var or --vars-file, which steps can use without exporting them first.
+// It returns the number of problems across all files.
+pub fn validate_files(files: &[PathBuf], vars: &HashSet<String>) -> usize {
+    // The variables of files that other files include can come from the `with` of the includes
+    let fragments: HashSet<PathBuf> = files
+        .iter()
+        .filter_map(|file| {
+            let source = fs::read_to_string(file).ok()?;
+            Some(include::includes(&source, &file.to_string_lossy()))
+        })
+        .flatten()
+        .collect();
+    let mut problems = 0;
+    for file in files {
+        let file_name = file.to_string_lossy().to_string();
+        let fragment = fragments.contains(&include::canonical(file));
+        let errors = match fs::read_to_string(file) {
+            Ok(source) => validate_source(&file_name, &source, fragment, vars),
+            Err(err) => {
+                eprintln!("❌ {}: {}", file_name, err);
+                problems += 1;
+                continue;
+            }
+        };
+        if errors.is_empty() {
+            eprintln!("✅ {}", file_name);
+            continue;
+        }
+        eprintln!("❌ {}", file_name);
+        for err in errors {
+            eprintln!("{}", report_error(err.into()));
+            problems += 1;
+        }
+    }
+    eprintln!(
+        "\nValidated {} files, found {} problems",
+        files.len(),
+        problems
+    );
+    problems
+}
+
+// validate parses a test file into steps without sending any requests, and checks for
+// mistakes that would otherwise only show up, or panic, at runtime.
+pub fn validate(file: &str, source: &str) -> Vec<ValidationError> {
+    validate_source(file, source, false, &HashSet::new())
+}
+
+// validate_source validates a file. The variables of a fragment, a file that other files
+// include, aren't checked, as they can be params of the includes.
+fn validate_source(
+    file: &str,
+    source: &str,
+    fragment: bool,
+    vars: &HashSet<String>,
+) -> Vec<ValidationError> {
+    let mut v = Validator {
+        file,
+        source,
+        steps: step_ranges(source),
+        entries: Vec::new(),
+        errors: Vec::new(),
+        fragment,
+        vars,
+    };
+    v.check_keys();
+    // Unknown keys and methods usually make the typed parse fail too, so they are reported on their own
+    if !v.errors.is_empty() {
+        return v.errors;
+    }
+    // Include steps can't be parsed as TestItems, so only files without them are parsed as a
+    // whole, which gives the errors a location
+    if !v
+        .entries
+        .iter()
+        .any(|(_, _, entry)| include::is_include(entry))
+    {
+        let doc: YamlValue = serde_yaml::from_str(source).unwrap_or_default();
+        let parsed = match doc.is_mapping() {
+            true => serde_yaml::from_str::<TestPlan>(source).map(|_| ()),
+            false => serde_yaml::from_str::<Vec<TestItem>>(source).map(|_| ()),
+        };
+        if let Err(err) = parsed {
+            let offset = err.location().map_or(0, |l| l.index());
+            v.error(err.to_string(), None, (offset, 0).into());
+            return v.errors;
+        }
+    }
+    // The steps of every section that runs before a step count as its earlier steps
+    let mut earlier = Vec::new();
+    for key in PLAN_KEYS {
+        v.check_section(key, &mut earlier);
+    }
+    v.errors
+}
+
+struct Validator<'a> {
+    file: &'a str,
+    source: &'a str,
+    // The byte range of every top level step in source
+    steps: Vec<Range<usize>>,
+    // Every top level step in source, with the section it is in and its index in the section
+    entries: Vec<(String, usize, YamlValue)>,
+    errors: Vec<ValidationError>,
+    fragment: bool,
+    // Variables set with --var or --vars-file
+    vars: &'a HashSet<String>,
+}
+
+impl<'a> Validator<'a> {
+    fn error(&mut self, message: String, advice: Option<String>, bad_bit: SourceSpan) {
+        self.errors.push(ValidationError {
+            message,
+            advice,
+            src: NamedSource::new(self.file, self.source.to_string()),
+            bad_bit,
+        });
+    }
+
+    // find locates needle within the given step. serde_yaml doesn't keep positions, so this
+    // is a text search that falls back to the start of the step.
+    fn find(&self, step: usize, needle: &str) -> SourceSpan {
+        let range = self
+            .steps
+            .get(step)
+            .cloned()
+            .unwrap_or(0..self.source.len());
+        match self.source[range.clone()].find(needle) {
+            Some(i) => (range.start + i, needle.len()).into(),
+            None => (range.start, 0).into(),
+        }
+    }
+
+    fn check_keys(&mut self) {
+        let doc = match serde_yaml::from_str::<YamlValue>(self.source) {
+            Ok(doc) => doc,
+            Err(err) => {
+                let offset = err.location().map_or(0, |l| l.index());
+                self.error(err.to_string(), None, (offset, 0).into());
+                return;
+            }
+        };
+        self.entries = match &doc {
+            YamlValue::Sequence(steps) => steps
+                .iter()
+                .enumerate()
+                .map(|(j, step)| ("steps".to_string(), j, step.clone()))
+                .collect(),
+            YamlValue::Mapping(plan) => self.plan_entries(plan),
+            _ => {
+                self.error(
+                    "a test file must be a list of steps, or a plan with hooks".to_string(),
+                    None,
+                    (0, 0).into(),
+                );
+                return;
+            }
+        };
+        for (i, (_, _, step)) in self.entries.clone().iter().enumerate() {
+            let Some(step) = step.as_mapping() else {
+                let span = self.find(i, "");
+                self.error(format!("{} is not a mapping", self.label(i)), None, span);
+                continue;
+            };
+            if step.contains_key("include") {
+                for key in step.keys().filter_map(|k| k.as_str()) {
+                    if key != "include" && key != "with" {
+                        let span = self.find(i, &format!("{}:", key));
+                        self.error(
+                            format!("unknown key `{}` in an include", key),
+                            Some("an include only takes `include` and `with`".to_string()),
+                            span,
+                        );
+                    }
+                }
+                continue;
+            }
+            let mut methods = 0;
+            for key in step.keys().filter_map(|k| k.as_str()) {
+                if HTTP_METHODS.contains(&key) {
+                    methods += 1;
+                } else if HTTP_METHODS.contains(&key.to_uppercase().as_str())
+                    || key.chars().all(|c| c.is_ascii_uppercase())
+                {
+                    let span = self.find(i, &format!("{}:", key));
+                    self.error(
+                        format!("invalid HTTP method `{}`", key),
+                        Some(format!(
+                            "use one of {}, or `method: {}` with `url:` for other methods",
+                            HTTP_METHODS.join(", "),
+                            key
+                        )),
+                        span,
+                    );
+                    methods += 1;
+                } else if key == "method" {
+                    methods += 1;
+                    let method = step.get("method").and_then(|m| m.as_str());
+                    if method.is_none_or(|m| HttpMethod::new(m, String::new()).is_none()) {
+                        let span = self.find(i, "method:");
+                        self.error(
+                            "invalid HTTP method".to_string(),
+                            Some("methods are words like PURGE or PROPFIND".to_string()),
+                            span,
+                        );
+                    }
+                } else if !STEP_KEYS.contains(&key) {
+                    let span = self.find(i, &format!("{}:", key));
+                    self.error(format!("unknown key `{}`", key), None, span);
+                }
+            }
+            if methods == 0 {
+                let span = self.find(i, "");
+                self.error(
+                    format!("{} has no HTTP method", self.label(i)),
+                    Some(format!(
+                        "add one of {} with the request url, or `method` and `url`",
+                        HTTP_METHODS.join(", ")
+                    )),
+                    span,
+                );
+            } else if methods > 1 {
+                let span = self.find(i, "");
+                self.error(
+                    format!("{} has more than one HTTP method", self.label(i)),
+                    None,
+                    span,
+                );
+            }
+            if step.contains_key("method") != step.contains_key("url") {
+                let span = self.find(i, "");
+                self.error(
+                    format!("{} needs both `method` and `url`", self.label(i)),
+                    None,
+                    span,
+                );
+            }
+            if let Some(asserts) = step.get("asserts").and_then(|a| a.as_sequence()) {
+                for assert in asserts {
+                    let Some(assert) = assert.as_mapping() else {
+                        continue;
+                    };
+                    for kind in assert.keys().filter_map(|k| k.as_str()) {
+                        if !ASSERT_KINDS.contains(&kind) {
+                            let span = self.find(i, &format!("{}:", kind));
+                            self.error(
+                                format!("unknown assertion `{}`", kind),
+                                Some(format!("use one of {}", ASSERT_KINDS.join(", "))),
+                                span,
+                            );
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // plan_entries returns the steps of a plan with hooks, in the order they are in the source
+    fn plan_entries(&mut self, plan: &serde_yaml::Mapping) -> Vec<(String, usize, YamlValue)> {
+        let mut entries = Vec::new();
+        for (key, value) in plan {
+            let key = key.as_str().unwrap_or_default();
+            if !PLAN_KEYS.contains(&key) {
+                let span = self.find(usize::MAX, &format!("{}:", key));
+                self.error(
+                    format!("unknown key `{}` in a plan", key),
+                    Some(format!("a plan takes {}", PLAN_KEYS.join(", "))),
+                    span,
+                );
+                continue;
+            }
+            let Some(steps) = value.as_sequence() else {
+                let span = self.find(usize::MAX, &format!("{}:", key));
+                self.error(format!("`{}` must be a list of steps", key), None, span);
+                continue;
+            };
+            for (j, step) in steps.iter().enumerate() {
+                entries.push((key.to_string(), j, step.clone()));
+            }
+        }
+        if !plan.contains_key("steps") {
+            self.error(
+                "a plan must have `steps`".to_string(),
+                Some("hooks run around the steps of a plan".to_string()),
+                (0, 0).into(),
+            );
+        }
+        entries
+    }
+
+    // label names a top level step in messages, eg `step 2` or `before_all step 0`
+    fn label(&self, i: usize) -> String {
+        match self.entries.get(i) {
+            Some((key, j, _)) if key != "steps" => format!("{} step {}", key, j),
+            Some((_, j, _)) => format!("step {}", j),
+            None => format!("step {}", i),
+        }
+    }
+
+    // check_section checks the steps of a section one by one. The steps of included files
+    // count as earlier steps.
+    fn check_section(&mut self, section: &str, earlier: &mut Vec<TestItem>) {
+        for (i, (key, _, entry)) in self.entries.clone().iter().enumerate() {
+            if key != section {
+                continue;
+            }
+            if include::is_include(entry) {
+                match include::include_steps(entry, self.file) {
+                    Ok(items) => earlier.extend(items),
+                    Err(err) => {
+                        let span = self.find(i, "include:");
+                        self.error(err.to_string(), None, span);
+                    }
+                }
+                continue;
+            }
+            match serde_yaml::from_value::<TestItem>(entry.clone()) {
+                Ok(item) => {
+                    self.check_step(i, &item, earlier);
+                    earlier.push(item);
+                }
+                Err(err) => {
+                    let span = self.find(i, "");
+                    self.error(format!("{}: {}", self.label(i), err), None, span);
+                }
+            }
+        }
+    }
+
+    fn check_step(&mut self, i: usize, item: &TestItem, earlier: &[TestItem]) {
+        for assert in item.asserts.iter().flatten() {
+            self.check_assert(i, assert);
+        }
+
+        for (key, condition) in [("if", &item.run_if), ("skip_if", &item.skip_if)] {
+            let Some(condition) = condition else {
+                continue;
+            };
+            // Conditions are evaluated before the request is sent
+            if let Some(path) = find_all_jsonpaths(condition).first() {
+                let span = self.find(i, path);
+                self.error(
+                    format!(
+                        "`{}` can't use the response, as it runs before the request",
+                        key
+                    ),
+                    Some("use the exports of earlier steps, eg `{{id}} != null`".to_string()),
+                    span,
+                );
+            }
+        }
+
+        if let Some(until) = item.retry.as_ref().and_then(|r| r.until.as_ref()) {
+            for path in find_all_jsonpaths(until) {
+                let span = self.find(i, path);
+                self.check_jsonpath(path, span);
+            }
+        }
+
+        for path in item.exports.iter().flat_map(|e| e.values()) {
+            // Headers and the status code are exported without going through jsonpath
+            if path.starts_with("$.res.header.") || path.starts_with("$.res.status.") {
+                continue;
+            }
+            let span = self.find(i, path);
+            self.check_jsonpath(path, span);
+        }
+
+        let mut exported: HashSet<&String> = earlier
+            .iter()
+            .flat_map(|t| t.exports.iter().flat_map(|e| e.keys()))
+            .collect();
+        // The columns of a for_each data set are variables of the step itself
+        let rows = match &item.for_each {
+            Some(for_each) => match data::rows(for_each, self.file) {
+                Ok(rows) => rows,
+                Err(err) => {
+                    let span = self.find(i, "for_each:");
+                    self.error(format!("invalid for_each data set: {}", err), None, span);
+                    vec![]
+                }
+            },
+            None => vec![],
+        };
+        exported.extend(rows.iter().flat_map(|row| row.keys()));
+        exported.extend(self.vars.iter());
+        if self.fragment {
+            return;
+        }
+        // `if` and `skip_if` may test for variables that only some runs export, eg `{{id}} != null`
+        let mut checked = item.clone();
+        checked.run_if = None;
+        checked.skip_if = None;
+        let step_source = serde_json::to_string(&checked).unwrap_or_default();
+        let mut reported = HashSet::new();
+        for var in get_vars(&step_source) {
+            let name = var.replace("{{", "").replace("}}", "");
+            if !exported.contains(&name) && reported.insert(name.clone()) {
+                let span = self.find(i, &var);
+                self.error(
+                    format!("`{}` is not exported by any earlier step", var),
+                    Some(format!(
+                        "add `{}` to the exports of a step before this one, or set it with --var",
+                        name
+                    )),
+                    span,
+                );
+            }
+        }
+    }
+
+    fn check_assert(&mut self, i: usize, assert: &Assert) {
+        let expr = assert.expr();
+        let expr_span = self.find(i, expr);
+        // Points at part of the expression, when the expression itself was found in the source
+        let sub_span = |part: &str| -> SourceSpan {
+            match expr.find(part) {
+                Some(j) if !expr_span.is_empty() => (expr_span.offset() + j, part.len()).into(),
+                _ => expr_span,
+            }
+        };
+        match assert {
+            Assert::IsOk(_) => {
+                for path in find_all_jsonpaths(expr) {
+                    self.check_jsonpath(path, sub_span(path));
+                }
+            }
+            Assert::IsDate(_) => {
+                let elements: Vec<&str> = expr.split_whitespace().collect();
+                if elements.len() < 2 {
+                    self.error(
+                        "date assertion is missing a date format".to_string(),
+                        Some(format!("eg `date: {} %Y-%m-%d`", expr.trim())),
+                        expr_span,
+                    );
+                }
+                if let Some(path) = elements.first() {
+                    self.check_jsonpath(path, sub_span(path));
+                }
+            }
+            Assert::Contains(_)
+            | Assert::NotContains(_)
+            | Assert::RegexMatch(_)
+            | Assert::NotRegexMatch(_) => {
+                let parts: Vec<&str> = expr.split('~').collect();
+                if parts.len() != 2 {
+                    self.error(
+                        format!("{} assertion must have exactly one `~`", assert.kind()),
+                        Some(format!("eg `{}: $.resp.json.name ~ value`", assert.kind())),
+                        expr_span,
+                    );
+                    return;
+                }
+                self.check_jsonpath(parts[0].trim(), sub_span(parts[0].trim()));
+                let is_regex = matches!(assert, Assert::RegexMatch(_) | Assert::NotRegexMatch(_));
+                // Regexes with exported variables can only be checked once the variables are known
+                if is_regex && get_vars(parts[1]).is_empty() {
+                    if let Err(err) = Regex::new(parts[1]) {
+                        self.error(
+                            "invalid regex".to_string(),
+                            Some(err.to_string()),
+                            sub_span(parts[1]),
+                        );
+                    }
+                }
+            }
+            _ => self.check_jsonpath(expr.trim(), expr_span),
+        }
+    }
+
+    fn check_jsonpath(&mut self, path: &str, span: SourceSpan) {
+        // Paths with exported variables can only be checked once the variables are known
+        if !get_vars(path).is_empty() {
+            return;
+        }
+        if let Err(err) = Compiled::compile(path) {
+            self.error(format!("malformed jsonpath `{}`", path), Some(err), span);
+        }
+    }
+}
+
+// step_ranges finds the byte range of every top level step, by looking for list items
+// at the indentation of the first one.
+fn step_ranges(source: &str) -> Vec<Range<usize>> {
+    let mut starts = Vec::new();
+    let mut indent = None;
+    let mut offset = 0;
+    for line in source.split_inclusive('\n') {
+        let trimmed = line.trim_start();
+        let line_indent = line.len() - trimmed.len();
+        if trimmed.starts_with('-') && indent.is_none_or(|i| i == line_indent) {
+            indent = Some(line_indent);
+            starts.push(offset + line_indent);
+        }
+        offset += line.len();
+    }
+    let mut ranges = Vec::new();
+    for (i, start) in starts.iter().enumerate() {
+        let end = starts.get(i + 1).copied().unwrap_or(source.len());
+        ranges.push(*start..end);
+    }
+    ranges
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate() {
+        let source = r#"
+- title: create todo
+  POST: http://localhost:3000/todos
+  headers:
+    Authorization: "{{token}}"
+  asserts:
+    - ok: $.resp.json[ == 5
+    - regexMatch: $.resp.json.task ~ ([a-z]
+    - date: $.resp.json.created_at
+  exports:
+    todoId: $.resp.json.id
+- title: get todo
+  GET: http://localhost:3000/todos/{{todoId}}
+  asserts:
+    - ok: $.resp.status == 200
+"#;
+        let errors = validate("todos.tk.yaml", source);
+        let messages: Vec<&str> = errors.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "malformed jsonpath `$.resp.json[`",
+                "invalid regex",
+                "date assertion is missing a date format",
+                "`{{token}}` is not exported by any earlier step",
+            ]
+        );
+        let span = errors[3].bad_bit;
+        assert_eq!(
+            &source[span.offset()..span.offset() + span.len()],
+            "{{token}}"
+        );
+
+        let source = r#"
+- title: bad keys
+  FETCH: http://localhost:3000/todos
+  asert:
+    - ok: $.resp.status == 200
+"#;
+        let errors = validate("todos.tk.yaml", source);
+        let messages: Vec<&str> = errors.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            vec!["invalid HTTP method `FETCH`", "unknown key `asert`"]
+        );
+
+        let source = r#"
+before_all:
+  - title: login
+    POST: http://localhost:3000/login
+    exports:
+      token: $.resp.json.token
+steps:
+  - title: list todos
+    GET: http://localhost:3000/todos
+    headers:
+      Authorization: "{{token}}"
+after_all:
+  - title: cleanup
+    DELETE: http://localhost:3000/todos/{{todoId}}
+"#;
+        let errors = validate("todos.tk.yaml", source);
+        let messages: Vec<&str> = errors.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            vec!["`{{todoId}}` is not exported by any earlier step"]
+        );
+        // Variables set with --var count as exported
+        let vars = HashSet::from(["todoId".to_string()]);
+        assert!(validate_source("todos.tk.yaml", source, false, &vars).is_empty());
+
+        // Conditions may test for variables that no step is sure to export
+        let source = r#"
+- title: delete todo
+  if: "{{todoId}} != null"
+  skip_if: "{{keepTodos}} == true"
+  DELETE: http://localhost:3000/todos
+"#;
+        assert!(validate("todos.tk.yaml", source).is_empty());
+
+        let source = "teardown: []\nafter_all:\n  - title: cleanup\n";
+        let errors = validate("todos.tk.yaml", source);
+        let messages: Vec<&str> = errors.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "unknown key `teardown` in a plan",
+                "a plan must have `steps`",
+                "after_all step 0 has no HTTP method",
+            ]
+        );
+    }
+}
@@ -0,0 +1,185 @@
+use crate::{TestRun, cli, environment, finish_run, include, is_tk_yaml_file, test_files};
+use notify::{Event, RecursiveMode, Watcher};
+use std::{
+    collections::{BTreeSet, HashSet},
+    env, fs,
+    io::{IsTerminal, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+use tokio::{sync::mpsc, time::timeout};
+
+// Editors often write a file in several steps, so events that arrive within this window
+// of each other are handled as a single change.
+const DEBOUNCE: Duration = Duration::from_millis(200);
+
+// watch runs the plans once, then re-runs a plan whenever its file, or a file it includes, is saved,
+// or every plan when the .env file, or that of the --env profile, changes. It only returns if the watcher fails.
+pub async fn watch(file_op: Option<PathBuf>, run: &TestRun) -> notify::Result<()> {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let mut watcher = notify::recommended_watcher(move |res: notify::Result<Event>| {
+        if let Ok(event) = res {
+            let _ = tx.send(event);
+        }
+    })?;
+    watcher.watch(Path::new("."), RecursiveMode::Recursive)?;
+
+    let cwd = env::current_dir().unwrap_or_default();
+    let only_file = file_op.as_ref().and_then(|f| f.canonicalize().ok());
+
+    rerun(test_files(file_op.clone()), run).await;
+    while let Some(event) = rx.recv().await {
+        let mut events = vec![event];
+        while let Ok(Some(event)) = timeout(DEBOUNCE, rx.recv()).await {
+            events.push(event);
+        }
+
+        let paths = events
+            .into_iter()
+            .filter(|e| !e.kind.is_access())
+            .flat_map(|e| e.paths);
+        let changes = changes(
+            paths,
+            test_files(file_op.clone()),
+            only_file.as_ref(),
+            &cwd,
+            run.environment.as_deref(),
+        );
+
+        if changes.env_changed {
+            environment::reload(run.environment.as_deref());
+            rerun(test_files(file_op.clone()), run).await;
+        } else if !changes.plans.is_empty() {
+            rerun(changes.plans.into_iter().collect(), run).await;
+        }
+    }
+    Ok(())
+}
+
+// Changes is what a batch of changed paths means for the run: every plan is re-run when an env
+// file changed, otherwise only the plans that changed or include a file that did.
+#[derive(Debug, Default, PartialEq)]
+struct Changes {
+    env_changed: bool,
+    plans: BTreeSet<PathBuf>,
+}
+
+// changes sorts the changed paths out. With a single watched file, only it and the plans that
+// include a changed file are re-run. Changed plans are relative to cwd, like the ones found at
+// startup.
+fn changes(
+    paths: impl IntoIterator<Item = PathBuf>,
+    plans: Vec<PathBuf>,
+    only_file: Option<&PathBuf>,
+    cwd: &Path,
+    environment: Option<&str>,
+) -> Changes {
+    let mut changes = Changes::default();
+    let mut changed_files = HashSet::new();
+    for path in paths {
+        if environment::is_env_file(&path, environment) {
+            changes.env_changed = true;
+        } else if is_tk_yaml_file(&path) && path.exists() {
+            let canonical = path.canonicalize().unwrap_or(path.clone());
+            changed_files.insert(canonical.clone());
+            if only_file.is_none_or(|f| f == &canonical) {
+                let relative = path.strip_prefix(cwd).map(Path::to_path_buf);
+                changes.plans.insert(relative.unwrap_or(path));
+            }
+        }
+    }
+    changes.plans.extend(including_plans(plans, changed_files));
+    changes
+}
+
+// including_plans returns the plans that include one of the changed files, directly or
+// through other plans. Plans that changed themselves are already re-run, so they're left out.
+fn including_plans(plans: Vec<PathBuf>, mut changed: HashSet<PathBuf>) -> Vec<PathBuf> {
+    let mut includes: Vec<(PathBuf, Vec<PathBuf>)> = plans
+        .into_iter()
+        .filter(|plan| !changed.contains(&include::canonical(plan)))
+        .map(|plan| {
+            let source = fs::read_to_string(&plan).unwrap_or_default();
+            let included = include::includes(&source, &plan.to_string_lossy());
+            (plan, included)
+        })
+        .collect();
+    let mut found = Vec::new();
+    while let Some(i) = includes
+        .iter()
+        .position(|(_, included)| included.iter().any(|f| changed.contains(f)))
+    {
+        let (plan, _) = includes.remove(i);
+        changed.insert(include::canonical(&plan));
+        found.push(plan);
+    }
+    found
+}
+
+async fn rerun(files: Vec<PathBuf>, run: &TestRun) {
+    // Clear the screen, so only the latest result is shown. Reports can be written to stdout, so
+    // the terminal is cleared through stderr, and only when it is one.
+    let mut stderr = std::io::stderr();
+    if stderr.is_terminal() {
+        let _ = write!(stderr, "\x1B[2J\x1B[1;1H");
+    }
+    let start = Instant::now();
+    let file_results = cli(files, run).await;
+    finish_run(&file_results, start, run);
+    eprintln!("👀 Watching for changes to .tk.yaml and .env files. Press Ctrl-C to exit.");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_changes() {
+        let dir = env::temp_dir().join("testkit-test-watch");
+        fs::create_dir_all(&dir).unwrap();
+        let dir = dir.canonicalize().unwrap();
+        fs::write(
+            dir.join("shared.tk.yaml"),
+            "- GET: http://localhost/login\n",
+        )
+        .unwrap();
+        fs::write(dir.join("a.tk.yaml"), "- include: shared.tk.yaml\n").unwrap();
+        fs::write(dir.join("b.tk.yaml"), "- GET: http://localhost/todos\n").unwrap();
+        fs::write(dir.join("notes.txt"), "").unwrap();
+        let plans: Vec<PathBuf> = ["a", "b", "shared"]
+            .iter()
+            .map(|name| dir.join(format!("{}.tk.yaml", name)))
+            .collect();
+        let changed = |names: &[&str], only_file: Option<&PathBuf>, environment| {
+            let paths = names.iter().map(|name| dir.join(name));
+            changes(paths, plans.clone(), only_file, &dir, environment)
+        };
+        let plan_set = |names: &[&str]| names.iter().map(PathBuf::from).collect::<BTreeSet<_>>();
+
+        // A changed plan is re-run, and so are the plans that include it
+        assert_eq!(
+            changed(&["shared.tk.yaml"], None, None).plans,
+            BTreeSet::from([dir.join("a.tk.yaml"), PathBuf::from("shared.tk.yaml")])
+        );
+        assert_eq!(
+            changed(&["b.tk.yaml", "notes.txt"], None, None).plans,
+            plan_set(&["b.tk.yaml"])
+        );
+        // Deleted files and other files are ignored
+        assert_eq!(
+            changed(&["gone.tk.yaml", "notes.txt"], None, None),
+            Changes::default()
+        );
+        // With a single watched file, other changed plans are left out
+        let only_a = dir.join("a.tk.yaml");
+        assert!(
+            changed(&["b.tk.yaml"], Some(&only_a), None)
+                .plans
+                .is_empty()
+        );
+        // The .env file, or that of the --env profile, re-runs everything
+        assert!(changed(&[".env"], None, None).env_changed);
+        assert!(changed(&[".env.staging"], None, Some("staging")).env_changed);
+        assert!(!changed(&[".env.staging"], None, None).env_changed);
+    }
+}